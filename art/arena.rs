@@ -0,0 +1,762 @@
+// ART whose nodes live in slabs owned by the tree, one slab per node type,
+// and point to each other by 32 bit references instead of pointers.
+//
+// A freed slot goes on the free list of its slab and the next node of that
+// type takes it again, so expand, shrink and delete reuse memory the tree
+// already has. Nodes of a type sit next to each other, and dropping the tree
+// frees a handful of vectors instead of walking every node.
+//
+// Nodes are not shared, so unlike Art there are no snapshots. ArtConfig and
+// its resize policies are not supported: the shrink thresholds are fixed to
+// the ones of Thresholds::default().
+
+use std::mem;
+
+// A child: the node type in the low 3 bits and its slot above them, 0 for none.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Ref(u32);
+
+const EMPTY: Ref = Ref(0);
+
+const LEAF: u32 = 1;
+const N4: u32 = 2;
+const N16: u32 = 3;
+const N48: u32 = 4;
+const N256: u32 = 5;
+
+impl Ref {
+    fn new(kind: u32, slot: usize) -> Ref {
+        assert!(slot < 1 << 29, "arena slab full");
+        Ref((slot as u32) << 3 | kind)
+    }
+
+    fn kind(self) -> u32 {
+        self.0 & 7
+    }
+
+    fn slot(self) -> usize {
+        (self.0 >> 3) as usize
+    }
+
+    fn is_empty(self) -> bool {
+        self == EMPTY
+    }
+}
+
+struct Slab<T> {
+    slots: Vec<Option<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Slab<T> {
+    fn new() -> Slab<T> {
+        Slab {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn alloc(&mut self, item: T) -> usize {
+        match self.free.pop() {
+            Some(i) => {
+                self.slots[i as usize] = Some(item);
+                i as usize
+            },
+            None => {
+                self.slots.push(Some(item));
+                self.slots.len() - 1
+            },
+        }
+    }
+
+    fn free(&mut self, i: usize) -> T {
+        self.free.push(i as u32);
+        self.slots[i].take().unwrap()
+    }
+
+    fn get(&self, i: usize) -> &T {
+        self.slots[i].as_ref().unwrap()
+    }
+
+    fn get_mut(&mut self, i: usize) -> &mut T {
+        self.slots[i].as_mut().unwrap()
+    }
+
+    fn live(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    fn memory(&self) -> usize {
+        self.slots.capacity() * mem::size_of::<Option<T>>() + self.free.capacity() * 4
+    }
+
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+    }
+}
+
+struct Header {
+    // compressed path below the edge of this node
+    prefix: Vec<u8>,
+    // the leaf of the key which ends at this node
+    leaf: Ref,
+    len: u16,
+}
+
+impl Header {
+    fn new(prefix: &[u8]) -> Header {
+        Header {
+            prefix: prefix.to_vec(),
+            leaf: EMPTY,
+            len: 0,
+        }
+    }
+}
+
+// keys sorted in the first len slots
+struct Node4 {
+    hdr: Header,
+    keys: [u8; 4],
+    children: [Ref; 4],
+}
+
+struct Node16 {
+    hdr: Header,
+    keys: [u8; 16],
+    children: [Ref; 16],
+}
+
+struct Node48 {
+    hdr: Header,
+    // slot of the child in children plus one, 0 if none
+    keys: [u8; 256],
+    children: [Ref; 48],
+}
+
+struct Node256 {
+    hdr: Header,
+    children: [Ref; 256],
+}
+
+struct Leaf<V> {
+    key: Vec<u8>,
+    value: V,
+}
+
+pub struct ArenaArt<V> {
+    root: Ref,
+    len: usize,
+    leaves: Slab<Leaf<V>>,
+    n4: Slab<Node4>,
+    n16: Slab<Node16>,
+    n48: Slab<Node48>,
+    n256: Slab<Node256>,
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let mut i = 0usize;
+    while i < a.len() && i < b.len() && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+// add key and child to the sorted keys and children of a Node4/16
+fn sorted_insert(keys: &mut [u8], children: &mut [Ref], len: usize, key: u8, ch: Ref) {
+    let mut i = len;
+    while i > 0 && keys[i - 1] > key {
+        keys[i] = keys[i - 1];
+        children[i] = children[i - 1];
+        i -= 1;
+    }
+    keys[i] = key;
+    children[i] = ch;
+}
+
+fn sorted_remove(keys: &mut [u8], children: &mut [Ref], len: usize, i: usize) {
+    for j in i + 1..len {
+        keys[j - 1] = keys[j];
+        children[j - 1] = children[j];
+    }
+    children[len - 1] = EMPTY;
+}
+
+impl<V> ArenaArt<V> {
+    pub fn new() -> ArenaArt<V> {
+        ArenaArt {
+            root: EMPTY,
+            len: 0,
+            leaves: Slab::new(),
+            n4: Slab::new(),
+            n16: Slab::new(),
+            n48: Slab::new(),
+            n256: Slab::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // inner nodes and leaves in use
+    pub fn get_size(&self) -> usize {
+        self.leaves.live() + self.n4.live() + self.n16.live() + self.n48.live() + self.n256.live()
+    }
+
+    // bytes held by the slabs, free slots included; keys and prefixes are not counted
+    pub fn memory(&self) -> usize {
+        self.leaves.memory() + self.n4.memory() + self.n16.memory() + self.n48.memory() + self.n256.memory()
+    }
+
+    // drop every key, the slabs keep their memory for the next inserts
+    pub fn clear(&mut self) {
+        self.root = EMPTY;
+        self.len = 0;
+        self.leaves.clear();
+        self.n4.clear();
+        self.n16.clear();
+        self.n48.clear();
+        self.n256.clear();
+    }
+
+    // set the value of key, returns the value it replaces
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, val: V) -> Option<V> {
+        let cs = key.as_ref();
+        let old = if self.root.is_empty() {
+            self.root = self.new_leaf(cs, val);
+            None
+        } else {
+            let (root, old) = self.insert_rec(self.root, cs, 0, val);
+            self.root = root;
+            old
+        };
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn search<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&V> {
+        let cs = key.as_ref();
+        let leaf = self.find_leaf(cs);
+        if leaf.is_empty() {
+            return None;
+        }
+        Some(&self.leaves.get(leaf.slot()).value)
+    }
+
+    pub fn search_mut<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<&mut V> {
+        let cs = key.as_ref();
+        let leaf = self.find_leaf(cs);
+        if leaf.is_empty() {
+            return None;
+        }
+        Some(&mut self.leaves.get_mut(leaf.slot()).value)
+    }
+
+    // remove key, returns its value
+    pub fn delete<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<V> {
+        if self.root.is_empty() {
+            return None;
+        }
+        let (root, val) = self.delete_rec(self.root, key.as_ref(), 0)?;
+        self.root = root;
+        self.len -= 1;
+        Some(val)
+    }
+
+    // keys and values in byte order
+    pub fn iter<'a>(&'a self) -> Iter<'a, V> {
+        Iter {
+            art: self,
+            stack: if self.root.is_empty() { Vec::new() } else { vec![self.root] },
+        }
+    }
+
+    fn find_leaf(&self, key: &[u8]) -> Ref {
+        let mut node = self.root;
+        let mut depth = 0usize;
+        while !node.is_empty() {
+            if node.kind() == LEAF {
+                if self.leaves.get(node.slot()).key.as_slice() != key {
+                    return EMPTY;
+                }
+                return node;
+            }
+            let hdr = self.hdr(node);
+            if !key[depth..].starts_with(&hdr.prefix) {
+                return EMPTY;
+            }
+            depth += hdr.prefix.len();
+            if depth == key.len() {
+                return hdr.leaf;
+            }
+            node = self.find_child(node, key[depth]);
+            depth += 1;
+        }
+        EMPTY
+    }
+
+    fn new_leaf(&mut self, key: &[u8], val: V) -> Ref {
+        let slot = self.leaves.alloc(Leaf {
+            key: key.to_vec(),
+            value: val,
+        });
+        Ref::new(LEAF, slot)
+    }
+
+    fn new_node4(&mut self, prefix: &[u8]) -> Ref {
+        let slot = self.n4.alloc(Node4 {
+            hdr: Header::new(prefix),
+            keys: [0; 4],
+            children: [EMPTY; 4],
+        });
+        Ref::new(N4, slot)
+    }
+
+    // put key below node, depth bytes of key are consumed by the path to
+    // node; returns what takes the place of node and the value replaced
+    fn insert_rec(&mut self, node: Ref, key: &[u8], depth: usize, val: V) -> (Ref, Option<V>) {
+        if node.kind() == LEAF {
+            let leaf = self.leaves.get_mut(node.slot());
+            if leaf.key.as_slice() == key {
+                return (node, Some(mem::replace(&mut leaf.value, val)));
+            }
+            // lazy expansion: a node4 takes the common part and both leaves
+            let p = common_prefix(&leaf.key[depth..], &key[depth..]);
+            let n = self.new_node4(&key[depth..depth + p]);
+            let n = self.put_leaf(n, depth + p, node);
+            let leaf = self.new_leaf(key, val);
+            return (self.put_leaf(n, depth + p, leaf), None);
+        }
+
+        let (p, plen) = {
+            let prefix = &self.hdr(node).prefix;
+            (common_prefix(prefix, &key[depth..]), prefix.len())
+        };
+        if p < plen {
+            // key differs inside the compressed prefix
+            let n = self.new_node4(&key[depth..depth + p]);
+            let edge = {
+                let prefix = &mut self.hdr_mut(node).prefix;
+                let edge = prefix[p];
+                prefix.drain(..p + 1);
+                edge
+            };
+            let n = self.add_child(n, edge, node);
+            let leaf = self.new_leaf(key, val);
+            return (self.put_leaf(n, depth + p, leaf), None);
+        }
+
+        let d = depth + plen;
+        if d == key.len() {
+            let own = self.hdr(node).leaf;
+            if !own.is_empty() {
+                return (node, Some(mem::replace(&mut self.leaves.get_mut(own.slot()).value, val)));
+            }
+            self.hdr_mut(node).leaf = self.new_leaf(key, val);
+            return (node, None);
+        }
+        let ch = self.find_child(node, key[d]);
+        if ch.is_empty() {
+            let leaf = self.new_leaf(key, val);
+            return (self.add_child(node, key[d], leaf), None);
+        }
+        let (new_ch, old) = self.insert_rec(ch, key, d + 1, val);
+        if new_ch != ch {
+            self.set_child(node, key[d], new_ch);
+        }
+        (node, old)
+    }
+
+    // hang leaf on node, at the header when its key ends at depth
+    fn put_leaf(&mut self, node: Ref, depth: usize, leaf: Ref) -> Ref {
+        let key = &self.leaves.get(leaf.slot()).key;
+        if key.len() == depth {
+            self.hdr_mut(node).leaf = leaf;
+            return node;
+        }
+        let c = key[depth];
+        self.add_child(node, c, leaf)
+    }
+
+    // remove key below node, returns what takes the place of node (EMPTY
+    // for none) and the value, or None when key is not there. An inner node
+    // keeps at least two entries, one left with a single one is replaced by it.
+    fn delete_rec(&mut self, node: Ref, key: &[u8], depth: usize) -> Option<(Ref, V)> {
+        if node.kind() == LEAF {
+            if self.leaves.get(node.slot()).key.as_slice() != key {
+                return None;
+            }
+            return Some((EMPTY, self.leaves.free(node.slot()).value));
+        }
+
+        let d = {
+            let prefix = &self.hdr(node).prefix;
+            if !key[depth..].starts_with(prefix) {
+                return None;
+            }
+            depth + prefix.len()
+        };
+        let val;
+        if d == key.len() {
+            let own = self.hdr(node).leaf;
+            if own.is_empty() {
+                return None;
+            }
+            self.hdr_mut(node).leaf = EMPTY;
+            val = self.leaves.free(own.slot()).value;
+        } else {
+            let ch = self.find_child(node, key[d]);
+            if ch.is_empty() {
+                return None;
+            }
+            let (new_ch, v) = self.delete_rec(ch, key, d + 1)?;
+            if new_ch.is_empty() {
+                self.del_child(node, key[d]);
+            } else if new_ch != ch {
+                self.set_child(node, key[d], new_ch);
+            }
+            val = v;
+        }
+
+        let (count, own) = {
+            let hdr = self.hdr(node);
+            (hdr.len as usize, hdr.leaf)
+        };
+        // only the leaf of this node is left
+        if count == 0 {
+            self.free_node(node);
+            return Some((own, val));
+        }
+        // single child, pull it up with the prefix of this node
+        if count == 1 && own.is_empty() {
+            let (c, ch) = self.children(node)[0];
+            let mut prefix = self.free_node(node).prefix;
+            if ch.kind() != LEAF {
+                prefix.push(c);
+                prefix.extend_from_slice(&self.hdr(ch).prefix);
+                self.hdr_mut(ch).prefix = prefix;
+            }
+            return Some((ch, val));
+        }
+        if self.need_shrink(node) {
+            return Some((self.shrink(node), val));
+        }
+        Some((node, val))
+    }
+
+    fn hdr(&self, node: Ref) -> &Header {
+        match node.kind() {
+            N4 => &self.n4.get(node.slot()).hdr,
+            N16 => &self.n16.get(node.slot()).hdr,
+            N48 => &self.n48.get(node.slot()).hdr,
+            N256 => &self.n256.get(node.slot()).hdr,
+            _ => unreachable!(),
+        }
+    }
+
+    fn hdr_mut(&mut self, node: Ref) -> &mut Header {
+        match node.kind() {
+            N4 => &mut self.n4.get_mut(node.slot()).hdr,
+            N16 => &mut self.n16.get_mut(node.slot()).hdr,
+            N48 => &mut self.n48.get_mut(node.slot()).hdr,
+            N256 => &mut self.n256.get_mut(node.slot()).hdr,
+            _ => unreachable!(),
+        }
+    }
+
+    // give the slot of an inner node back, returns its header
+    fn free_node(&mut self, node: Ref) -> Header {
+        match node.kind() {
+            N4 => self.n4.free(node.slot()).hdr,
+            N16 => self.n16.free(node.slot()).hdr,
+            N48 => self.n48.free(node.slot()).hdr,
+            N256 => self.n256.free(node.slot()).hdr,
+            _ => unreachable!(),
+        }
+    }
+
+    fn find_child(&self, node: Ref, c: u8) -> Ref {
+        match node.kind() {
+            N4 => {
+                let n = self.n4.get(node.slot());
+                for i in 0..n.hdr.len as usize {
+                    if n.keys[i] == c {
+                        return n.children[i];
+                    }
+                }
+                EMPTY
+            },
+            N16 => {
+                let n = self.n16.get(node.slot());
+                match n.keys[..n.hdr.len as usize].binary_search(&c) {
+                    Ok(i) => n.children[i],
+                    Err(_) => EMPTY,
+                }
+            },
+            N48 => {
+                let n = self.n48.get(node.slot());
+                match n.keys[c as usize] {
+                    0 => EMPTY,
+                    i => n.children[i as usize - 1],
+                }
+            },
+            N256 => self.n256.get(node.slot()).children[c as usize],
+            _ => EMPTY,
+        }
+    }
+
+    // replace the child at edge c, which is there
+    fn set_child(&mut self, node: Ref, c: u8, ch: Ref) {
+        match node.kind() {
+            N4 => {
+                let n = self.n4.get_mut(node.slot());
+                let i = n.keys[..n.hdr.len as usize].iter().position(|&k| k == c).unwrap();
+                n.children[i] = ch;
+            },
+            N16 => {
+                let n = self.n16.get_mut(node.slot());
+                let i = n.keys[..n.hdr.len as usize].binary_search(&c).unwrap();
+                n.children[i] = ch;
+            },
+            N48 => {
+                let n = self.n48.get_mut(node.slot());
+                let i = n.keys[c as usize] as usize - 1;
+                n.children[i] = ch;
+            },
+            N256 => self.n256.get_mut(node.slot()).children[c as usize] = ch,
+            _ => unreachable!(),
+        }
+    }
+
+    // add a child at edge c, expanding a full node; returns what takes the
+    // place of node
+    fn add_child(&mut self, node: Ref, c: u8, ch: Ref) -> Ref {
+        let node = if self.need_expand(node) { self.expand(node) } else { node };
+        match node.kind() {
+            N4 => {
+                let n = self.n4.get_mut(node.slot());
+                sorted_insert(&mut n.keys, &mut n.children, n.hdr.len as usize, c, ch);
+                n.hdr.len += 1;
+            },
+            N16 => {
+                let n = self.n16.get_mut(node.slot());
+                sorted_insert(&mut n.keys, &mut n.children, n.hdr.len as usize, c, ch);
+                n.hdr.len += 1;
+            },
+            N48 => {
+                let n = self.n48.get_mut(node.slot());
+                let i = n.children.iter().position(|ch| ch.is_empty()).unwrap();
+                n.children[i] = ch;
+                n.keys[c as usize] = i as u8 + 1;
+                n.hdr.len += 1;
+            },
+            N256 => {
+                let n = self.n256.get_mut(node.slot());
+                n.children[c as usize] = ch;
+                n.hdr.len += 1;
+            },
+            _ => unreachable!(),
+        }
+        node
+    }
+
+    fn del_child(&mut self, node: Ref, c: u8) {
+        match node.kind() {
+            N4 => {
+                let n = self.n4.get_mut(node.slot());
+                let len = n.hdr.len as usize;
+                let i = n.keys[..len].iter().position(|&k| k == c).unwrap();
+                sorted_remove(&mut n.keys, &mut n.children, len, i);
+                n.hdr.len -= 1;
+            },
+            N16 => {
+                let n = self.n16.get_mut(node.slot());
+                let len = n.hdr.len as usize;
+                let i = n.keys[..len].binary_search(&c).unwrap();
+                sorted_remove(&mut n.keys, &mut n.children, len, i);
+                n.hdr.len -= 1;
+            },
+            N48 => {
+                let n = self.n48.get_mut(node.slot());
+                let i = n.keys[c as usize] as usize - 1;
+                n.keys[c as usize] = 0;
+                n.children[i] = EMPTY;
+                n.hdr.len -= 1;
+            },
+            N256 => {
+                let n = self.n256.get_mut(node.slot());
+                n.children[c as usize] = EMPTY;
+                n.hdr.len -= 1;
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // edges and children in key order
+    fn children(&self, node: Ref) -> Vec<(u8, Ref)> {
+        match node.kind() {
+            N4 => {
+                let n = self.n4.get(node.slot());
+                (0..n.hdr.len as usize).map(|i| (n.keys[i], n.children[i])).collect()
+            },
+            N16 => {
+                let n = self.n16.get(node.slot());
+                (0..n.hdr.len as usize).map(|i| (n.keys[i], n.children[i])).collect()
+            },
+            N48 => {
+                let n = self.n48.get(node.slot());
+                (0..256).filter(|&c| n.keys[c] != 0)
+                        .map(|c| (c as u8, n.children[n.keys[c] as usize - 1])).collect()
+            },
+            N256 => {
+                let n = self.n256.get(node.slot());
+                (0..256).filter(|&c| !n.children[c].is_empty())
+                        .map(|c| (c as u8, n.children[c])).collect()
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn need_expand(&self, node: Ref) -> bool {
+        let len = self.hdr(node).len;
+        match node.kind() {
+            N4 => len == 4,
+            N16 => len == 16,
+            N48 => len == 48,
+            _ => false,
+        }
+    }
+
+    fn need_shrink(&self, node: Ref) -> bool {
+        let len = self.hdr(node).len;
+        match node.kind() {
+            N16 => len < 3,
+            N48 => len < 15,
+            N256 => len < 46,
+            _ => false,
+        }
+    }
+
+    // move a node into a free slot of the next bigger type
+    fn expand(&mut self, node: Ref) -> Ref {
+        let children = self.children(node);
+        let hdr = self.free_node(node);
+        match node.kind() {
+            N4 => {
+                let mut n = Node16 {
+                    hdr,
+                    keys: [0; 16],
+                    children: [EMPTY; 16],
+                };
+                for (i, &(c, ch)) in children.iter().enumerate() {
+                    n.keys[i] = c;
+                    n.children[i] = ch;
+                }
+                Ref::new(N16, self.n16.alloc(n))
+            },
+            N16 => {
+                let mut n = Node48 {
+                    hdr,
+                    keys: [0; 256],
+                    children: [EMPTY; 48],
+                };
+                for (i, &(c, ch)) in children.iter().enumerate() {
+                    n.keys[c as usize] = i as u8 + 1;
+                    n.children[i] = ch;
+                }
+                Ref::new(N48, self.n48.alloc(n))
+            },
+            N48 => {
+                let mut n = Node256 {
+                    hdr,
+                    children: [EMPTY; 256],
+                };
+                for &(c, ch) in children.iter() {
+                    n.children[c as usize] = ch;
+                }
+                Ref::new(N256, self.n256.alloc(n))
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // move a node into a free slot of the next smaller type
+    fn shrink(&mut self, node: Ref) -> Ref {
+        let children = self.children(node);
+        let hdr = self.free_node(node);
+        match node.kind() {
+            N16 => {
+                let mut n = Node4 {
+                    hdr,
+                    keys: [0; 4],
+                    children: [EMPTY; 4],
+                };
+                for (i, &(c, ch)) in children.iter().enumerate() {
+                    n.keys[i] = c;
+                    n.children[i] = ch;
+                }
+                Ref::new(N4, self.n4.alloc(n))
+            },
+            N48 => {
+                let mut n = Node16 {
+                    hdr,
+                    keys: [0; 16],
+                    children: [EMPTY; 16],
+                };
+                for (i, &(c, ch)) in children.iter().enumerate() {
+                    n.keys[i] = c;
+                    n.children[i] = ch;
+                }
+                Ref::new(N16, self.n16.alloc(n))
+            },
+            N256 => {
+                let mut n = Node48 {
+                    hdr,
+                    keys: [0; 256],
+                    children: [EMPTY; 48],
+                };
+                for (i, &(c, ch)) in children.iter().enumerate() {
+                    n.keys[c as usize] = i as u8 + 1;
+                    n.children[i] = ch;
+                }
+                Ref::new(N48, self.n48.alloc(n))
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Walks the tree depth first. The own leaf of a node has the shortest key
+// below it, so it comes before the children.
+pub struct Iter<'a, V> {
+    art: &'a ArenaArt<V>,
+    stack: Vec<Ref>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (&'a [u8], &'a V);
+
+    fn next(&mut self) -> Option<(&'a [u8], &'a V)> {
+        loop {
+            let node = self.stack.pop()?;
+            if node.kind() == LEAF {
+                let leaf = self.art.leaves.get(node.slot());
+                return Some((&leaf.key, &leaf.value));
+            }
+            for &(_, ch) in self.art.children(node).iter().rev() {
+                self.stack.push(ch);
+            }
+            let own = self.art.hdr(node).leaf;
+            if !own.is_empty() {
+                self.stack.push(own);
+            }
+        }
+    }
+}
@@ -1,1117 +1,2813 @@
-use std::u8;
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::mem;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Operations shared by the inner node types. Children are owned by their
+// node, so a node never knows its parent: expand/shrink hand back the new
+// node and the caller puts it in the slot the old one was taken from.
+pub trait ArtNode<V> {
+    fn header(&self) -> &Header<V>;
+    fn header_mut(&mut self) -> &mut Header<V>;
+
+    fn find_child(&self, key: u8) -> Option<&Node<V>>;
+    fn find_child_mut(&mut self, key: u8) -> Option<&mut Node<V>>;
+
+    // caller makes sure the node is not full, see need_expand
+    fn add_child(&mut self, key: u8, ch: Node<V>);
+    fn del_child(&mut self, key: u8) -> Option<Node<V>>;
+
+    // edges and children in this node, in key order
+    fn children(&self) -> Vec<(u8, &Node<V>)>;
+    fn child_count(&self) -> usize;
+
+    // nearest child at or after/before key, for ordered walks
+    fn child_at_or_after(&self, key: u8) -> Option<(u8, &Node<V>)>;
+    fn child_at_or_before(&self, key: u8) -> Option<(u8, &Node<V>)>;
+
+    // keys in the subtrees of the children at edges less than key
+    fn keys_before(&self, key: u8) -> usize;
+
+    // keys, children and len agree, for validate
+    fn check(&self) -> Result<(), &'static str>;
+
+    fn need_expand(&self) -> bool;
+    fn need_shrink(&self, policy: &dyn ResizePolicy) -> bool;
+
+    // move header and children into a bigger/smaller node, leaving self empty
+    fn expand(&mut self) -> Node<V>;
+    fn shrink(&mut self) -> Node<V>;
+}
 
-pub trait ArtNode {
-    fn shrink(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        None 
-    }
-    fn expand(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        None 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    N4,
+    N16,
+    N48,
+    N256,
+}
+
+// When an inner node moves to the next smaller type. A node grows when it is
+// full, only shrinking is a choice: shrinking late keeps a node which loses
+// and gains children around a boundary from changing type on every write.
+pub trait ResizePolicy: Send + Sync {
+    // len children are left in a node of kind, deletes were made in it since
+    // it was made; never asked for N4. A yes is only taken once the children
+    // fit in the smaller type.
+    fn shrink(&self, kind: NodeKind, len: usize, deletes: usize) -> bool;
+}
+
+// shrink once the children drop below a number per type
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    node16: usize,
+    node48: usize,
+    node256: usize,
+}
+
+impl Thresholds {
+    // the children of a node under its threshold must fit in the smaller type
+    pub fn new(node16: usize, node48: usize, node256: usize) -> Thresholds {
+        assert!(node16 <= 5 && node48 <= 17 && node256 <= 49,
+                "shrink thresholds above the size of the smaller node");
+        Thresholds {
+            node16,
+            node48,
+            node256,
+        }
     }
-    
-    fn get_edge(&self) -> u8 {
-        0 as u8
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds::new(3, 15, 46)
     }
+}
 
-    fn need_expand(&self) -> bool {
-        false
+impl ResizePolicy for Thresholds {
+    fn shrink(&self, kind: NodeKind, len: usize, _: usize) -> bool {
+        match kind {
+            NodeKind::N4 => false,
+            NodeKind::N16 => len < self.node16,
+            NodeKind::N48 => len < self.node48,
+            NodeKind::N256 => len < self.node256,
+        }
     }
-    fn need_shrink(&self) -> bool {
-        false
+}
+
+// shrink as policy says, but not before a node has seen deletes deletes
+#[derive(Clone, Copy, Debug)]
+pub struct DelayedShrink<P> {
+    pub policy: P,
+    pub deletes: usize,
+}
+
+impl<P: ResizePolicy> ResizePolicy for DelayedShrink<P> {
+    fn shrink(&self, kind: NodeKind, len: usize, deletes: usize) -> bool {
+        deletes >= self.deletes && self.policy.shrink(kind, len, deletes)
     }
+}
 
-    fn set_value(&mut self, _val: String) {}
-    fn get_values(&self) -> Option<Vec<String>> {
-        None
+#[derive(Clone)]
+pub struct ArtConfig {
+    pub resize: Arc<dyn ResizePolicy>,
+}
+
+impl Default for ArtConfig {
+    fn default() -> ArtConfig {
+        ArtConfig {
+            resize: Arc::new(Thresholds::default()),
+        }
     }
-    fn del_values(&mut self) {}
+}
 
-    fn set_child(&mut self, key: u8, ch: Option<Rc<RefCell<dyn ArtNode>>>) -> bool;
-    fn get_child(&mut self, key: u8) -> Option<Rc<RefCell<dyn ArtNode>>>;
+// common part of the inner nodes
+pub struct Header<V> {
+    // compressed path below the edge of this node
+    prefix: Vec<u8>,
+    // the key which ends at this node
+    leaf: Option<Arc<Leaf<V>>>,
+    // keys in the subtree, the own leaf included
+    count: usize,
+    // children removed since the node was made, for the resize policy
+    deletes: u32,
+    // digest of the subtree, 0 while unknown. Filled in by digest and diff,
+    // cleared when the node is changed, so after a write only the digests
+    // on its path are computed again.
+    digest: AtomicU64,
+}
 
-    fn del_child(&mut self, key: u8);
+impl<V> Header<V> {
+    fn new(prefix: &[u8]) -> Header<V> {
+        Header {
+            prefix: prefix.to_vec(),
+            leaf: None,
+            count: 0,
+            deletes: 0,
+            digest: AtomicU64::new(0),
+        }
+    }
 
-    fn get_children(&mut self) -> Vec<Option<Rc<RefCell<dyn ArtNode>>>> {
-        Vec::new()
+    // the header for the node which replaces this one
+    fn take(&mut self) -> Header<V> {
+        let mut hdr = mem::replace(self, Header::new(&[]));
+        hdr.deletes = 0;
+        hdr
     }
+}
 
-    fn empty(&self) -> bool {
-        false
+// Nodes are cloned by hand, a copy shares the children and leaves
+// and needs no V: Clone.
+impl<V> Clone for Header<V> {
+    fn clone(&self) -> Header<V> {
+        Header {
+            prefix: self.prefix.clone(),
+            leaf: self.leaf.clone(),
+            count: self.count,
+            deletes: self.deletes,
+            digest: AtomicU64::new(self.digest.load(Ordering::Relaxed)),
+        }
     }
+}
+
+pub enum Node<V> {
+    N4(Arc<Node4<V>>),
+    N16(Arc<Node16<V>>),
+    N48(Arc<Node48<V>>),
+    N256(Arc<Node256<V>>),
+    Leaf(Arc<Leaf<V>>),
+}
 
-    fn set_parent(&mut self, _pare: Option<Rc<RefCell<dyn ArtNode>>>) {}
-    fn get_parent(&self) -> Option<Rc<RefCell<dyn ArtNode>>> { None }
+impl<V> Clone for Node<V> {
+    fn clone(&self) -> Node<V> {
+        match *self {
+            Node::N4(ref n) => Node::N4(n.clone()),
+            Node::N16(ref n) => Node::N16(n.clone()),
+            Node::N48(ref n) => Node::N48(n.clone()),
+            Node::N256(ref n) => Node::N256(n.clone()),
+            Node::Leaf(ref l) => Node::Leaf(l.clone()),
+        }
+    }
 }
 
+impl<V> Node<V> {
+    fn inner(&self) -> Option<&dyn ArtNode<V>> {
+        match *self {
+            Node::N4(ref n) => Some(&**n),
+            Node::N16(ref n) => Some(&**n),
+            Node::N48(ref n) => Some(&**n),
+            Node::N256(ref n) => Some(&**n),
+            Node::Leaf(_) => None,
+        }
+    }
+
+    // a node shared with a snapshot is copied before it is changed, so the
+    // path down to a change is copied and the rest stays shared
+    fn inner_mut(&mut self) -> Option<&mut dyn ArtNode<V>> {
+        let n = match *self {
+            Node::N4(ref mut n) => Arc::make_mut(n) as &mut dyn ArtNode<V>,
+            Node::N16(ref mut n) => Arc::make_mut(n) as &mut dyn ArtNode<V>,
+            Node::N48(ref mut n) => Arc::make_mut(n) as &mut dyn ArtNode<V>,
+            Node::N256(ref mut n) => Arc::make_mut(n) as &mut dyn ArtNode<V>,
+            Node::Leaf(_) => return None,
+        };
+        *n.header_mut().digest.get_mut() = 0;
+        Some(n)
+    }
+
+    // The policy asks to shrink and the children fit in the smaller type.
+    // A policy may say yes too early, the node then waits for more deletes.
+    fn should_shrink(&self, policy: &dyn ResizePolicy) -> bool {
+        let fits = match *self {
+            Node::N16(_) => 4,
+            Node::N48(_) => 16,
+            Node::N256(_) => 48,
+            _ => return false,
+        };
+        let n = self.inner().unwrap();
+        n.child_count() <= fits && n.need_shrink(policy)
+    }
+
+    // keys in the subtree of this node
+    fn key_count(&self) -> usize {
+        match self.inner() {
+            Some(n) => n.header().count,
+            None => 1,
+        }
+    }
+
+    // static dispatch for the lookup path
+    fn find_child(&self, key: u8) -> Option<&Node<V>> {
+        match *self {
+            Node::N4(ref n) => n.find_child(key),
+            Node::N16(ref n) => n.find_child(key),
+            Node::N48(ref n) => n.find_child(key),
+            Node::N256(ref n) => n.find_child(key),
+            Node::Leaf(_) => None,
+        }
+    }
+}
 
-pub struct Art {
-    sentinel: Rc<RefCell<dyn ArtNode>>,
-    root: Option<Rc<RefCell<dyn ArtNode>>>,
+pub struct Art<V> {
+    root: Option<Node<V>>,
     size: usize,
+    // copies a value out of a leaf shared with a snapshot, set by snapshot()
+    clone_value: Option<fn(&V) -> V>,
+    // held by every live snapshot, set by snapshot()
+    snapshots: Option<Arc<()>>,
+    config: ArtConfig,
 }
 
-impl Art {
+// A read-only view of an Art as it was when taken, see Art::snapshot.
+// It derefs to the tree for search, range and the other reads.
+pub struct Snapshot<V> {
+    art: Art<V>,
+    _live: Arc<()>,
+}
+
+impl<V> Deref for Snapshot<V> {
+    type Target = Art<V>;
+
+    fn deref(&self) -> &Art<V> {
+        &self.art
+    }
+}
+
+// bulk_load got a key which is not greater than the one before it
+#[derive(Debug, PartialEq)]
+pub struct UnsortedError {
+    // position of the key in the input
+    pub index: usize,
+    pub key: Vec<u8>,
+}
+
+impl fmt::Display for UnsortedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "key {:?} at {} is not greater than the key before it", self.key, self.index)
+    }
+}
+
+// a problem validate found, at the key path of the node
+#[derive(Debug, PartialEq)]
+pub struct InvalidTree {
+    pub path: Vec<u8>,
+    pub problem: &'static str,
+}
+
+impl fmt::Display for InvalidTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:?}", self.problem, self.path)
+    }
+}
 
-    pub fn new() -> Art {
-        let mut art = Art {
-            sentinel: Rc::new(RefCell::new(Sentinel::new())),
+// shape of a tree, see Art::stats
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub node4: usize,
+    pub node16: usize,
+    pub node48: usize,
+    pub node256: usize,
+    pub leaves: usize,
+    // keys by the number of inner nodes above them
+    pub depths: Vec<usize>,
+    // bytes of nodes, leaves and their keys and prefixes
+    pub memory: usize,
+    // children over slots of all inner nodes
+    pub fill: f64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "node4 {}, node16 {}, node48 {}, node256 {}, leaves {}",
+                 self.node4, self.node16, self.node48, self.node256, self.leaves)?;
+        writeln!(f, "memory {} bytes, fill {:.1}%", self.memory, self.fill * 100.0)?;
+        write!(f, "keys by depth {:?}", self.depths)
+    }
+}
+
+// what to_dot_with draws
+#[derive(Debug, Default)]
+pub struct DotOptions {
+    // levels of inner nodes below the first one, deeper subtrees are cut off
+    pub max_depth: Option<usize>,
+    // only the subtree of the keys starting with this
+    pub prefix: Vec<u8>,
+}
+
+impl<V> Art<V> {
+
+    pub fn new() -> Art<V> {
+        Art::with_config(ArtConfig::default())
+    }
+
+    pub fn with_config(config: ArtConfig) -> Art<V> {
+        Art {
             root: None,
-            size: 1usize,
-        };
-        let mut node4 = Node4::new(0);
-        node4.parent = Some(art.sentinel.clone());
-        let rc = Rc::new(RefCell::new(node4)); 
-        art.sentinel.borrow_mut().set_child(0, Some(rc.clone()));
-        art.root = Some(rc.clone());
-        art
-    }
-
-    pub fn insert(&mut self, key: String, val: String) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let cs = key.as_bytes();
-        if self.root.is_none() {
-            println!("root can't be None");
+            size: 0usize,
+            clone_value: None,
+            snapshots: None,
+            config,
+        }
+    }
+
+    // build a tree from keys in strictly increasing order. Every node is made
+    // at its final type once its children are known, nothing gets expanded.
+    pub fn bulk_load<K, I>(sorted: I) -> Result<Art<V>, UnsortedError>
+        where K: AsRef<[u8]>, I: IntoIterator<Item = (K, V)> {
+        Art::bulk_load_with(sorted, ArtConfig::default())
+    }
+
+    pub fn bulk_load_with<K, I>(sorted: I, config: ArtConfig) -> Result<Art<V>, UnsortedError>
+        where K: AsRef<[u8]>, I: IntoIterator<Item = (K, V)> {
+        let mut leaves: Vec<Option<Arc<Leaf<V>>>> = Vec::new();
+        for (i, (key, val)) in sorted.into_iter().enumerate() {
+            let cs = key.as_ref();
+            if let Some(Some(prev)) = leaves.last() {
+                if prev.key.as_slice() >= cs {
+                    return Err(UnsortedError {
+                        index: i,
+                        key: cs.to_vec(),
+                    });
+                }
+            }
+            leaves.push(Some(Leaf::new(cs, val)));
+        }
+
+        if leaves.is_empty() {
+            return Ok(Art::with_config(config));
+        }
+        let (root, size) = build_rec(&mut leaves, 0);
+        Ok(Art {
+            root: Some(root),
+            size,
+            clone_value: None,
+            snapshots: None,
+            config,
+        })
+    }
+
+    // The tree as it is now, unchanged by later writes. Taking it copies
+    // nothing: the nodes are shared, and a write copies the nodes on its
+    // path from the root down to the leaf it changes, while the snapshot
+    // keeps the old ones.
+    pub fn snapshot(&mut self) -> Snapshot<V> where V: Clone {
+        self.clone_value = Some(V::clone);
+        let live = self.snapshots.get_or_insert_with(|| Arc::new(())).clone();
+        Snapshot {
+            art: Art {
+                root: self.root.clone(),
+                size: self.size,
+                clone_value: self.clone_value,
+                snapshots: None,
+                config: self.config.clone(),
+            },
+            _live: live,
+        }
+    }
+
+    // whether a snapshot may still share nodes with the tree
+    fn shared(&self) -> bool {
+        self.snapshots.as_ref().is_some_and(|s| Arc::strong_count(s) > 1)
+    }
+
+    // set the value of key, returns the value it replaces
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, val: V) -> Option<V> {
+        let mut val = Some(val);
+        let (added, slot) = self.entry(key.as_ref(), || val.take().unwrap());
+        if added {
             return None;
         }
+        Some(mem::replace(slot, val.unwrap()))
+    }
 
-        let mut curr_node = self.root.as_ref().unwrap().clone();
-        for c in cs.iter() {
-            let cn = curr_node.borrow_mut().get_child(*c as u8);
-            // child node exists
-            if cn.is_some() {
-                curr_node = cn.as_ref().unwrap().clone();
-                continue;
-            }
-            // child node not exists
-            let mut node4 = Node4::new(*c as u8);
-            node4.parent = Some(curr_node.clone());
-            let rc = Rc::new(RefCell::new(node4)); 
-
-            // set a new child node for c
-            if curr_node.borrow_mut().set_child(*c as u8, Some(rc.clone())) {
-                curr_node = rc.clone();
-                self.size += 1;
-                continue;
-            }
+    // the value of key, made by make when key is missing
+    pub fn get_or_insert_with<K, F>(&mut self, key: &K, make: F) -> &mut V
+        where K: AsRef<[u8]> + ?Sized, F: FnOnce() -> V {
+        self.entry(key.as_ref(), make).1
+    }
 
-            // set child node failed, maybe need expand
-            if curr_node.borrow_mut().need_expand() {
-                let mut candi = curr_node.borrow_mut().expand();
-                if candi.is_none() {
-                    println!("expand node error");
-                    return None;
-                }
-                if !candi.as_mut().unwrap().borrow_mut().set_child(*c as u8, Some(rc.clone())) {
-                    println!("still set child failed after expand");
-                    return None;
+    // set key to f of its current value, or of None when it is missing;
+    // returns the value it replaces
+    pub fn upsert<K, F>(&mut self, key: &K, f: F) -> Option<V>
+        where K: AsRef<[u8]> + ?Sized, F: FnOnce(Option<&V>) -> V {
+        let mut f = Some(f);
+        let (added, slot) = self.entry(key.as_ref(), || f.take().unwrap()(None));
+        if added {
+            return None;
+        }
+        let val = f.unwrap()(Some(&*slot));
+        Some(mem::replace(slot, val))
+    }
+
+    // change the value of key in place, returns what f returns or None
+    // when key is missing
+    pub fn update<K, F, R>(&mut self, key: &K, f: F) -> Option<R>
+        where K: AsRef<[u8]> + ?Sized, F: FnOnce(&mut V) -> R {
+        self.search_mut(key).map(f)
+    }
+
+    // Replace the value of key by new if it is equal to expected, returns
+    // the old value. Otherwise new is handed back with the current value,
+    // None when key is missing.
+    pub fn compare_and_swap<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, expected: &V, new: V)
+        -> Result<V, (V, Option<&V>)> where V: PartialEq {
+        match self.search_mut(key) {
+            Some(val) => {
+                if *val == *expected {
+                    Ok(mem::replace(val, new))
+                } else {
+                    Err((new, Some(val)))
                 }
-                // set parent's child to new node
-                let k = curr_node.borrow_mut().get_edge();
-                let p = curr_node.borrow_mut().get_parent();
-                match p {
-                    Some(ref prc) => prc.borrow_mut().set_child(k, candi),
-                    None => {
-                        println!("Parent node can't be None");
+            },
+            None => Err((new, None)),
+        }
+    }
+
+    // one walk down for the operations which insert a missing key
+    fn entry<F: FnOnce() -> V>(&mut self, key: &[u8], make: F) -> (bool, &mut V) {
+        let (n, added, slot) = match self.root {
+            Some(ref mut node) => entry_rec(node, key, 0, make, self.clone_value),
+            None => {
+                let mut leaf = Leaf::new(key, make());
+                let slot = value_ptr(&mut leaf, self.clone_value);
+                self.root = Some(Node::Leaf(leaf));
+                (1, true, slot)
+            },
+        };
+        self.size += n;
+        // SAFETY: slot points into a leaf which value_ptr made unique, see
+        // there. The walk after it only moves the Arc of the leaf between
+        // nodes, it never clones or drops it, so the leaf is alive and owned
+        // by this tree alone, and self stays borrowed as long as the result.
+        (added, unsafe { &mut *slot })
+    }
+
+    pub fn search<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&V> {
+        let cs = key.as_ref();
+        let mut curr_node = match self.root {
+            Some(ref node) => node,
+            None => return None,
+        };
+        let mut depth = 0usize;
+
+        loop {
+            let leaf = match *curr_node {
+                Node::Leaf(ref leaf) => leaf,
+                _ => {
+                    let hdr = curr_node.inner().unwrap().header();
+                    if !cs[depth..].starts_with(&hdr.prefix) {
                         return None;
-                    },
-                };
-                self.size += 1;
-                curr_node = rc.clone();
-            } else {
+                    }
+                    depth += hdr.prefix.len();
+                    if depth < cs.len() {
+                        match curr_node.find_child(cs[depth]) {
+                            Some(ch) => {
+                                curr_node = ch;
+                                depth += 1;
+                                continue;
+                            },
+                            None => return None,
+                        }
+                    }
+                    match hdr.leaf {
+                        Some(ref leaf) => leaf,
+                        None => return None,
+                    }
+                },
+            };
+            if leaf.key.as_slice() != cs {
                 return None;
             }
+            return Some(&leaf.value);
+        }
+    }
+
+    // Calls f with every key which is a prefix of key, key itself included,
+    // shortest first. One walk down the path of key.
+    pub fn for_each_prefix<'a, K, F>(&'a self, key: &K, mut f: F)
+        where K: AsRef<[u8]> + ?Sized, F: FnMut(&'a [u8], &'a V) {
+        let cs = key.as_ref();
+        let mut curr_node = match self.root {
+            Some(ref node) => node,
+            None => return,
+        };
+        let mut depth = 0usize;
+
+        loop {
+            let hdr = match *curr_node {
+                Node::Leaf(ref leaf) => {
+                    if cs.starts_with(&leaf.key) {
+                        f(&leaf.key, &leaf.value);
+                    }
+                    return;
+                },
+                _ => curr_node.inner().unwrap().header(),
+            };
+            if !cs[depth..].starts_with(&hdr.prefix) {
+                return;
+            }
+            depth += hdr.prefix.len();
+            if let Some(ref leaf) = hdr.leaf {
+                f(&leaf.key, &leaf.value);
+            }
+            if depth == cs.len() {
+                return;
+            }
+            match curr_node.find_child(cs[depth]) {
+                Some(ch) => {
+                    curr_node = ch;
+                    depth += 1;
+                },
+                None => return,
+            }
         }
-        curr_node.borrow_mut().set_value(val);
-        Some(curr_node.clone())
     }
 
-    pub fn search(&self, key: &String) -> Option<Vec<String>> {
-        let cs = key.as_bytes();
-        if self.root.is_none() {
-            println!("root can't be None");
+    pub fn search_mut<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<&mut V> {
+        let cs = key.as_ref();
+        // going down copies the nodes shared with a snapshot, not worth it for a miss
+        if self.shared() && self.search(cs).is_none() {
             return None;
         }
+        let clone_value = self.clone_value;
+        let mut curr_node = match self.root {
+            Some(ref mut node) => node,
+            None => return None,
+        };
+        let mut depth = 0usize;
 
-        let mut curr_node = self.root.as_ref().unwrap().clone();
-        for c in cs.iter() {
-            let cn = curr_node.borrow_mut().get_child(*c as u8);
-            if cn.is_none() {
-                println!("Search: no such key: {}", key);
+        loop {
+            // the prefix is checked on the way down, the leaf key at the end
+            let d = match curr_node.inner() {
+                Some(n) => {
+                    let prefix = &n.header().prefix;
+                    if !cs[depth..].starts_with(prefix) {
+                        return None;
+                    }
+                    depth + prefix.len()
+                },
+                None => depth,
+            };
+            let leaf = match *curr_node {
+                Node::Leaf(ref mut leaf) => leaf,
+                _ if d == cs.len() => match curr_node.inner_mut().unwrap().header_mut().leaf {
+                    Some(ref mut leaf) => leaf,
+                    None => return None,
+                },
+                _ => match curr_node.inner_mut().unwrap().find_child_mut(cs[d]) {
+                    Some(ch) => {
+                        curr_node = ch;
+                        depth = d + 1;
+                        continue;
+                    },
+                    None => return None,
+                },
+            };
+            if leaf.key.as_slice() != cs {
                 return None;
             }
-            curr_node = cn.as_ref().unwrap().clone();
+            return Some(&mut leaf_mut(leaf, clone_value).value);
+        }
+    }
+
+    // remove key, returns its value
+    pub fn delete<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<V> {
+        let cs = key.as_ref();
+        let res = match self.root {
+            // the only key is in the root leaf
+            Some(Node::Leaf(ref leaf)) => {
+                if leaf.key.as_slice() != cs {
+                    return None;
+                }
+                None
+            },
+            Some(ref mut node) => match delete_rec(node, cs, 0, self.clone_value, &*self.config.resize) {
+                Some(res) => Some(res),
+                None => return None,
+            },
+            None => return None,
+        };
+
+        match res {
+            Some((n, val)) => {
+                self.size -= n;
+                Some(val)
+            },
+            None => {
+                self.size = 0;
+                match self.root.take() {
+                    Some(Node::Leaf(leaf)) => Some(leaf_value(leaf, self.clone_value)),
+                    _ => None,
+                }
+            },
+        }
+    }
+
+    // remove every key in range, returns the number of removed keys;
+    // subtrees inside the range are dropped whole
+    pub fn delete_range<K, R>(&mut self, range: R) -> usize
+        where K: AsRef<[u8]> + ?Sized, R: RangeBounds<K> {
+        self.delete_bounded(own_bound(range.start_bound()), own_bound(range.end_bound()))
+    }
+
+    // remove all keys starting with prefix
+    pub fn delete_prefix<K: AsRef<[u8]> + ?Sized>(&mut self, prefix: &K) -> usize {
+        let (lower, upper) = prefix_bounds(prefix.as_ref());
+        self.delete_bounded(lower, upper)
+    }
+
+    fn delete_bounded(&mut self, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>) -> usize {
+        let cover = match self.root {
+            Some(Node::Leaf(ref leaf)) => leaf_cover(&leaf.key, &lower, &upper),
+            Some(ref node) => cover(&node.inner().unwrap().header().prefix, &lower, &upper),
+            None => Cover::Outside,
+        };
+        match cover {
+            Cover::Outside => 0,
+            Cover::Inside => {
+                let keys = self.len();
+                self.root = None;
+                self.size = 0;
+                keys
+            },
+            Cover::Partial => {
+                let node = self.root.as_mut().unwrap();
+                let (keys, freed, empty) = delete_range_rec(node, &mut Vec::new(), &lower, &upper,
+                                                              &*self.config.resize);
+                if empty {
+                    self.root = None;
+                }
+                self.size -= freed;
+                keys
+            },
         }
+    }
 
-        let r = curr_node.borrow_mut().get_values();
-        r
+    // digest of all keys and values, equal for trees with the same content
+    pub fn digest(&self) -> u64 where V: Hash {
+        match self.root {
+            Some(ref node) => node_digest(node),
+            None => 0,
+        }
     }
 
-    pub fn delete(&mut self, key: &String) -> bool {
-        let cs = key.as_bytes();
-        let mut curr_node = self.root.as_ref().unwrap().clone();
+    // Keys whose values differ between the two trees or which only one of
+    // them has, in byte order. Subtrees with the same digest are skipped.
+    pub fn diff(&self, other: &Art<V>) -> Vec<Vec<u8>> where V: Hash {
+        let mut keys = Vec::new();
+        let a = self.root.as_ref().map(|n| Side::new(n, &[]));
+        let b = other.root.as_ref().map(|n| Side::new(n, &[]));
+        diff_rec(&mut Vec::new(), a, b, &mut keys);
+        keys
+    }
 
-        for c in cs.iter() {
-            let cn = curr_node.borrow_mut().get_child(*c as u8);
-            if cn.is_none() {
-                println!("Delete: no such key: {}", key);
-                return false;
+    // Check the shape of the tree: key/child agreement in every node, key
+    // paths, fill thresholds, key counts and size. Returns the first problem.
+    pub fn validate(&self) -> Result<(), InvalidTree> {
+        let nodes = match self.root {
+            Some(ref node) => validate_rec(node, &mut Vec::new(), &*self.config.resize)?.1,
+            None => 0,
+        };
+        if nodes != self.size {
+            return Err(InvalidTree {
+                path: Vec::new(),
+                problem: "size is not the number of nodes",
+            });
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        let mut slots = 0usize;
+        let mut used = 0usize;
+        let mut stack: Vec<(&Node<V>, usize)> = self.root.iter().map(|n| (n, 0)).collect();
+        while let Some((node, depth)) = stack.pop() {
+            let n = match *node {
+                Node::Leaf(ref leaf) => {
+                    stats.leaves += 1;
+                    stats.memory += leaf_memory(leaf);
+                    if stats.depths.len() <= depth {
+                        stats.depths.resize(depth + 1, 0);
+                    }
+                    stats.depths[depth] += 1;
+                    continue;
+                },
+                Node::N4(ref n) => {
+                    stats.node4 += 1;
+                    stats.memory += mem::size_of::<Node4<V>>();
+                    slots += 4;
+                    &**n as &dyn ArtNode<V>
+                },
+                Node::N16(ref n) => {
+                    stats.node16 += 1;
+                    stats.memory += mem::size_of::<Node16<V>>();
+                    slots += 16;
+                    &**n as &dyn ArtNode<V>
+                },
+                Node::N48(ref n) => {
+                    stats.node48 += 1;
+                    stats.memory += mem::size_of::<Node48<V>>();
+                    slots += 48;
+                    &**n as &dyn ArtNode<V>
+                },
+                Node::N256(ref n) => {
+                    stats.node256 += 1;
+                    stats.memory += mem::size_of::<Node256<V>>();
+                    slots += 256;
+                    &**n as &dyn ArtNode<V>
+                },
+            };
+            // the Arc counts in front of the node
+            stats.memory += 2 * mem::size_of::<usize>() + n.header().prefix.capacity();
+            used += n.child_count();
+            if let Some(ref leaf) = n.header().leaf {
+                stats.leaves += 1;
+                stats.memory += leaf_memory(leaf);
+                if stats.depths.len() <= depth + 1 {
+                    stats.depths.resize(depth + 2, 0);
+                }
+                stats.depths[depth + 1] += 1;
+            }
+            for (_, ch) in n.children().into_iter().rev() {
+                stack.push((ch, depth + 1));
             }
-            curr_node = cn.as_ref().unwrap().clone();
         }
+        if slots > 0 {
+            stats.fill = used as f64 / slots as f64;
+        }
+        stats
+    }
 
-        // key not exist
-        if curr_node.borrow_mut().get_values().as_ref().unwrap().len() == 0 {
-            println!("Delete: no values of key: {}", key);
-            return false;
+    // write the tree as a Graphviz graph, for `dot -Tsvg`
+    pub fn to_dot<W: Write>(&self, w: W) -> io::Result<()> where V: fmt::Debug {
+        self.to_dot_with(w, &DotOptions::default())
+    }
+
+    pub fn to_dot_with<W: Write>(&self, mut w: W, opts: &DotOptions) -> io::Result<()>
+        where V: fmt::Debug {
+        writeln!(w, "digraph art {{")?;
+        writeln!(w, "    node [shape=box, fontname=monospace];")?;
+        // go down to the subtree holding the keys with the prefix
+        let mut curr = self.root.as_ref();
+        let mut depth = 0usize;
+        while let Some(node) = curr {
+            let n = match node.inner() {
+                Some(n) => n,
+                None => {
+                    if !node_leaf_key(node).starts_with(&opts.prefix) {
+                        curr = None;
+                    }
+                    break;
+                },
+            };
+            let prefix = &n.header().prefix;
+            let rest = &opts.prefix[depth.min(opts.prefix.len())..];
+            if rest.len() <= prefix.len() {
+                if !prefix.starts_with(rest) {
+                    curr = None;
+                }
+                break;
+            }
+            if !rest.starts_with(prefix) {
+                curr = None;
+                break;
+            }
+            depth += prefix.len();
+            curr = node.find_child(opts.prefix[depth]);
+            depth += 1;
+        }
+        if let Some(node) = curr {
+            dot_rec(&mut w, node, 0, opts.max_depth, &mut 0)?;
         }
+        writeln!(w, "}}")
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    // number of keys
+    pub fn len(&self) -> usize {
+        match self.root {
+            Some(ref node) => node.key_count(),
+            None => 0,
+        }
+    }
 
-        // delete values
-        curr_node.borrow_mut().del_values();
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // keys and values whose key falls in range, in byte order;
+    // the iterator can also be walked from the back with rev()
+    pub fn range<'a, K, R>(&'a self, range: R) -> Range<'a, V>
+        where K: AsRef<[u8]> + ?Sized, R: RangeBounds<K> {
+        self.bounded(own_bound(range.start_bound()), own_bound(range.end_bound()))
+    }
+
+    fn bounded<'a>(&'a self, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>) -> Range<'a, V> {
+        Range {
+            root: self.root.as_ref(),
+            front: Vec::new(),
+            back: Vec::new(),
+            lower,
+            upper,
+            last_front: None,
+            last_back: None,
+            front_started: false,
+            back_started: false,
+            done: false,
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> Range<'a, V> {
+        self.range::<[u8], _>(..)
+    }
+
+    // all keys starting with prefix
+    pub fn prefix_iter<'a, K: AsRef<[u8]> + ?Sized>(&'a self, prefix: &K) -> Range<'a, V> {
+        let (lower, upper) = prefix_bounds(prefix.as_ref());
+        self.bounded(lower, upper)
+    }
+
+    pub fn first(&self) -> Option<(&[u8], &V)> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<(&[u8], &V)> {
+        self.iter().next_back()
+    }
+
+    // the smallest key greater than key
+    pub fn successor<'a, K: AsRef<[u8]> + ?Sized>(&'a self, key: &K)
+        -> Option<(&'a [u8], &'a V)> {
+        self.range::<[u8], _>((Bound::Excluded(key.as_ref()), Bound::Unbounded)).next()
+    }
+
+    // the greatest key less than key
+    pub fn predecessor<'a, K: AsRef<[u8]> + ?Sized>(&'a self, key: &K)
+        -> Option<(&'a [u8], &'a V)> {
+        self.range::<[u8], _>((Bound::Unbounded, Bound::Excluded(key.as_ref()))).next_back()
+    }
+
+    // number of keys less than key; the subtree counts of the children left
+    // of the path are added up on the way down
+    pub fn rank<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> usize {
+        let cs = key.as_ref();
+        let mut curr_node = match self.root {
+            Some(ref node) => node,
+            None => return 0,
+        };
+        let mut depth = 0usize;
+        let mut rank = 0usize;
 
-        // if no sub keys exist, curr_node should be deleted,
-        // also some node may need shrink
         loop {
-            // empty node
-            if curr_node.borrow_mut().empty() {
-                if Rc::ptr_eq(self.root.as_ref().unwrap(), &curr_node) {
+            let n = match curr_node.inner() {
+                Some(n) => n,
+                None => {
+                    if let Node::Leaf(ref leaf) = *curr_node {
+                        if leaf.key.as_slice() < cs {
+                            rank += 1;
+                        }
+                    }
+                    return rank;
+                },
+            };
+            let hdr = n.header();
+            let rest = &cs[depth..];
+            let l = hdr.prefix.len().min(rest.len());
+            if rest[..l] != hdr.prefix[..l] {
+                // the whole subtree is on one side of key
+                if rest[..l] > hdr.prefix[..l] {
+                    rank += hdr.count;
+                }
+                return rank;
+            }
+            if l < hdr.prefix.len() {
+                // key ends inside the prefix, every key below is greater
+                return rank;
+            }
+            depth += l;
+            if depth == cs.len() {
+                return rank;
+            }
+            if hdr.leaf.is_some() {
+                rank += 1;
+            }
+            rank += n.keys_before(cs[depth]);
+            match n.find_child(cs[depth]) {
+                Some(ch) => {
+                    curr_node = ch;
+                    depth += 1;
+                },
+                None => return rank,
+            }
+        }
+    }
+
+    // the key at position i in key order, counting from 0
+    pub fn select(&self, i: usize) -> Option<(&[u8], &V)> {
+        let mut curr_node = match self.root {
+            Some(ref node) => node,
+            None => return None,
+        };
+        if i >= curr_node.key_count() {
+            return None;
+        }
+        let mut i = i;
+
+        loop {
+            let n = match *curr_node {
+                Node::Leaf(ref leaf) => return Some((&leaf.key, &leaf.value)),
+                _ => curr_node.inner().unwrap(),
+            };
+            if let Some(ref leaf) = n.header().leaf {
+                if i == 0 {
+                    return Some((&leaf.key, &leaf.value));
+                }
+                i -= 1;
+            }
+            // skip whole children until the one holding position i
+            let mut next = n.child_at_or_after(0);
+            while let Some((c, ch)) = next {
+                let count = ch.key_count();
+                if i < count {
                     break;
                 }
-                let key = curr_node.borrow_mut().get_edge();
-                let p = curr_node.borrow_mut().get_parent();
-                match p {
-                    Some(ref rc) => {
-                        println!("delete node: {}", key as char);
-                        rc.borrow_mut().del_child(key);
-                        self.size -= 1;
+                i -= count;
+                next = if c == u8::MAX { None } else { n.child_at_or_after(c + 1) };
+            }
+            curr_node = next.unwrap().1;
+        }
+    }
+
+    // number of keys in range, from the ranks of its ends
+    pub fn count_range<K, R>(&self, range: R) -> usize
+        where K: AsRef<[u8]> + ?Sized, R: RangeBounds<K> {
+        let start = match range.start_bound() {
+            Bound::Included(k) => self.rank(k),
+            Bound::Excluded(k) => self.rank(k) + self.search(k).is_some() as usize,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(k) => self.rank(k) + self.search(k).is_some() as usize,
+            Bound::Excluded(k) => self.rank(k),
+            Bound::Unbounded => self.len(),
+        };
+        end.saturating_sub(start)
+    }
+}
+
+// several values under one key
+impl<V> Art<Vec<V>> {
+    pub fn push<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, val: V) {
+        self.get_or_insert_with(key, Vec::new).push(val);
+    }
+}
+
+fn own_bound<K: AsRef<[u8]> + ?Sized>(b: Bound<&K>) -> Bound<Vec<u8>> {
+    match b {
+        Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+        Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+// bounds of the keys starting with prefix
+fn prefix_bounds(prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    // the first key after every key with this prefix
+    let mut upper = prefix.to_vec();
+    while upper.last() == Some(&u8::MAX) {
+        upper.pop();
+    }
+    let end = match upper.pop() {
+        Some(c) => {
+            upper.push(c + 1);
+            Bound::Excluded(upper)
+        },
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(prefix.to_vec()), end)
+}
+
+// key is greater than or equal to the lower bound
+fn above_lower(key: &[u8], lower: &Bound<Vec<u8>>) -> bool {
+    match *lower {
+        Bound::Included(ref k) => key >= k.as_slice(),
+        Bound::Excluded(ref k) => key > k.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+// key is less than or equal to the upper bound
+fn below_upper(key: &[u8], upper: &Bound<Vec<u8>>) -> bool {
+    match *upper {
+        Bound::Included(ref k) => key <= k.as_slice(),
+        Bound::Excluded(ref k) => key < k.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+struct Frame<'a, V> {
+    node: &'a Node<V>,
+    // next edge to look at, moves up from the front and down from the back
+    next: i32,
+    // leaf of this node not visited yet
+    pending: bool,
+}
+
+enum Step<'a, V> {
+    Visit(&'a Leaf<V>),
+    Down(&'a Node<V>),
+    Up(Option<&'a Leaf<V>>),
+    Skip,
+}
+
+pub struct Range<'a, V> {
+    root: Option<&'a Node<V>>,
+    front: Vec<Frame<'a, V>>,
+    back: Vec<Frame<'a, V>>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    last_front: Option<&'a [u8]>,
+    last_back: Option<&'a [u8]>,
+    front_started: bool,
+    back_started: bool,
+    done: bool,
+}
+
+impl<'a, V> Range<'a, V> {
+
+    // put the front cursor on the path of the lower bound, subtrees before it
+    // are skipped without being walked; keys on the path are checked in next()
+    fn seek_front(&mut self) {
+        let mut node = match self.root {
+            Some(node) => node,
+            None => return,
+        };
+        let k = match self.lower {
+            Bound::Included(ref k) | Bound::Excluded(ref k) => k.clone(),
+            Bound::Unbounded => Vec::new(),
+        };
+        let mut depth = 0usize;
+
+        while let Some(n) = node.inner() {
+            let prefix = &n.header().prefix;
+            let end = if k.len() < depth + prefix.len() { k.len() } else { depth + prefix.len() };
+            let seg = &k[depth..end];
+            if prefix[..seg.len()] < *seg {
+                return;
+            }
+            // the whole subtree is after the bound
+            if prefix[..seg.len()] > *seg || end < depth + prefix.len() {
+                break;
+            }
+            depth = end;
+            if depth == k.len() {
+                break;
+            }
+            let c = k[depth];
+            self.front.push(Frame { node, next: c as i32 + 1, pending: false });
+            match node.find_child(c) {
+                Some(ch) => {
+                    node = ch;
+                    depth += 1;
+                },
+                None => return,
+            }
+        }
+        self.front.push(Frame { node, next: 0, pending: true });
+    }
+
+    // put the back cursor on the path of the upper bound
+    fn seek_back(&mut self) {
+        let mut node = match self.root {
+            Some(node) => node,
+            None => return,
+        };
+        let k = match self.upper {
+            Bound::Included(ref k) | Bound::Excluded(ref k) => k.clone(),
+            Bound::Unbounded => {
+                self.back.push(Frame { node, next: u8::MAX as i32, pending: true });
+                return;
+            },
+        };
+        let mut depth = 0usize;
+
+        while let Some(n) = node.inner() {
+            let prefix = &n.header().prefix;
+            let end = if k.len() < depth + prefix.len() { k.len() } else { depth + prefix.len() };
+            let seg = &k[depth..end];
+            // the whole subtree is before the bound
+            if prefix[..seg.len()] < *seg {
+                break;
+            }
+            if prefix[..seg.len()] > *seg || end < depth + prefix.len() {
+                return;
+            }
+            depth = end;
+            if depth == k.len() {
+                self.back.push(Frame { node, next: -1, pending: true });
+                return;
+            }
+            let c = k[depth];
+            self.back.push(Frame { node, next: c as i32 - 1, pending: true });
+            match node.find_child(c) {
+                Some(ch) => {
+                    node = ch;
+                    depth += 1;
+                },
+                None => return,
+            }
+        }
+        self.back.push(Frame { node, next: u8::MAX as i32, pending: true });
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        self.front.clear();
+        self.back.clear();
+    }
+}
+
+impl<'a, V> Iterator for Range<'a, V> {
+    type Item = (&'a [u8], &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.front_started {
+            self.front_started = true;
+            self.seek_front();
+        }
+
+        while !self.done {
+            let step = match self.front.last_mut() {
+                Some(top) => match *top.node {
+                    Node::Leaf(ref leaf) => Step::Up(Some(&**leaf)),
+                    ref node => {
+                        let n = node.inner().unwrap();
+                        if top.pending {
+                            top.pending = false;
+                            match n.header().leaf {
+                                Some(ref leaf) => Step::Visit(&**leaf),
+                                None => Step::Skip,
+                            }
+                        } else {
+                            let found = if top.next <= u8::MAX as i32 {
+                                n.child_at_or_after(top.next as u8)
+                            } else {
+                                None
+                            };
+                            match found {
+                                Some((c, ch)) => {
+                                    top.next = c as i32 + 1;
+                                    Step::Down(ch)
+                                },
+                                None => Step::Up(None),
+                            }
+                        }
+                    },
+                },
+                None => break,
+            };
+
+            let leaf = match step {
+                Step::Visit(leaf) => leaf,
+                Step::Down(ch) => {
+                    self.front.push(Frame { node: ch, next: 0, pending: true });
+                    continue;
+                },
+                Step::Up(leaf) => {
+                    self.front.pop();
+                    match leaf {
+                        Some(leaf) => leaf,
+                        None => continue,
                     }
-                    None => {
-                        println!("parent can't be None, current key: {}", key as char);
-                        return false;
+                },
+                Step::Skip => continue,
+            };
+
+            let key = leaf.key.as_slice();
+            if !above_lower(key, &self.lower) {
+                continue;
+            }
+            let behind = match self.last_back {
+                Some(b) => key >= b,
+                None => false,
+            };
+            if behind || !below_upper(key, &self.upper) {
+                break;
+            }
+            self.last_front = Some(key);
+            return Some((key, &leaf.value));
+        }
+        self.finish();
+        None
+    }
+}
+
+impl<'a, V> DoubleEndedIterator for Range<'a, V> {
+
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if !self.back_started {
+            self.back_started = true;
+            self.seek_back();
+        }
+
+        while !self.done {
+            let step = match self.back.last_mut() {
+                Some(top) => match *top.node {
+                    Node::Leaf(ref leaf) => Step::Up(Some(&**leaf)),
+                    ref node => {
+                        let n = node.inner().unwrap();
+                        let found = if top.next >= 0 {
+                            n.child_at_or_before(top.next as u8)
+                        } else {
+                            None
+                        };
+                        match found {
+                            Some((c, ch)) => {
+                                top.next = c as i32 - 1;
+                                Step::Down(ch)
+                            },
+                            // children all visited, now the leaf of the node
+                            None if top.pending => {
+                                top.next = -1;
+                                top.pending = false;
+                                match n.header().leaf {
+                                    Some(ref leaf) => Step::Visit(&**leaf),
+                                    None => Step::Skip,
+                                }
+                            },
+                            None => Step::Up(None),
+                        }
                     },
-                }
-                curr_node = p.as_ref().unwrap().clone();
-                continue
+                },
+                None => break,
+            };
+
+            let leaf = match step {
+                Step::Visit(leaf) => leaf,
+                Step::Down(ch) => {
+                    self.back.push(Frame { node: ch, next: u8::MAX as i32, pending: true });
+                    continue;
+                },
+                Step::Up(leaf) => {
+                    self.back.pop();
+                    match leaf {
+                        Some(leaf) => leaf,
+                        None => continue,
+                    }
+                },
+                Step::Skip => continue,
+            };
+
+            let key = leaf.key.as_slice();
+            if !below_upper(key, &self.upper) {
+                continue;
+            }
+            let behind = match self.last_front {
+                Some(f) => key <= f,
+                None => false,
+            };
+            if behind || !above_lower(key, &self.lower) {
+                break;
+            }
+            self.last_back = Some(key);
+            return Some((key, &leaf.value));
+        }
+        self.finish();
+        None
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let mut i = 0usize;
+    while i < a.len() && i < b.len() && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+// find key below node or put it there with the value of make, depth bytes of
+// key are consumed by the path to node; returns the number of nodes created,
+// whether key was added and where its value is
+fn entry_rec<V, F: FnOnce() -> V>(node: &mut Node<V>, key: &[u8], depth: usize, make: F,
+                                  clone_value: Option<fn(&V) -> V>) -> (usize, bool, *mut V) {
+    let split = match *node {
+        Node::Leaf(ref mut leaf) => {
+            if leaf.key.as_slice() == key {
+                return (0, false, value_ptr(leaf, clone_value));
             }
-            // not empty, but need shrink
-            if curr_node.borrow_mut().need_shrink() {
-                let candi = curr_node.borrow_mut().shrink();
-                if candi.is_none() {
-                    println!("shrink node failed");
-                    return false;
+            common_prefix(&leaf.key[depth..], &key[depth..])
+        },
+        _ => {
+            let prefix = &node.inner().unwrap().header().prefix;
+            let p = common_prefix(prefix, &key[depth..]);
+            if p == prefix.len() {
+                return entry_inner(node, key, depth + p, make, clone_value);
+            }
+            p
+        },
+    };
+
+    // lazy expansion: another key is in the leaf, or key differs inside the
+    // compressed prefix. A node4 takes the common part and holds both.
+    let new_node = Node::N4(Arc::new(Node4::new(&key[depth..depth + split])));
+    let old = mem::replace(node, new_node);
+    node.inner_mut().unwrap().header_mut().count = old.key_count() + 1;
+    let d = depth + split;
+    match old {
+        Node::Leaf(leaf) => put_leaf(node, d, leaf),
+        mut old => {
+            let edge = {
+                let hdr = old.inner_mut().unwrap().header_mut();
+                let edge = hdr.prefix[split];
+                hdr.prefix.drain(..split + 1);
+                edge
+            };
+            node.inner_mut().unwrap().add_child(edge, old);
+        },
+    }
+    let mut leaf = Leaf::new(key, make());
+    let slot = value_ptr(&mut leaf, clone_value);
+    put_leaf(node, d, leaf);
+    (2, true, slot)
+}
+
+// key matches the whole path to inner node, d bytes of it are consumed
+fn entry_inner<V, F: FnOnce() -> V>(node: &mut Node<V>, key: &[u8], d: usize, make: F,
+                                    clone_value: Option<fn(&V) -> V>) -> (usize, bool, *mut V) {
+    if d == key.len() {
+        let hdr = node.inner_mut().unwrap().header_mut();
+        if let Some(ref mut leaf) = hdr.leaf {
+            return (0, false, value_ptr(leaf, clone_value));
+        }
+        let mut leaf = Leaf::new(key, make());
+        let slot = value_ptr(&mut leaf, clone_value);
+        hdr.leaf = Some(leaf);
+        hdr.count += 1;
+        return (1, true, slot);
+    }
+
+    if let Some(ch) = node.inner_mut().unwrap().find_child_mut(key[d]) {
+        let (n, added, slot) = entry_rec(ch, key, d + 1, make, clone_value);
+        if added {
+            node.inner_mut().unwrap().header_mut().count += 1;
+        }
+        return (n, added, slot);
+    }
+    let mut leaf = Leaf::new(key, make());
+    let slot = value_ptr(&mut leaf, clone_value);
+    put_leaf(node, d, leaf);
+    node.inner_mut().unwrap().header_mut().count += 1;
+    (1, true, slot)
+}
+
+// hang leaf on inner node, at the header when its key ends at depth, or
+// below the edge key[depth], expanding node if it is full
+fn put_leaf<V>(node: &mut Node<V>, depth: usize, leaf: Arc<Leaf<V>>) {
+    if depth == leaf.key.len() {
+        node.inner_mut().unwrap().header_mut().leaf = Some(leaf);
+        return;
+    }
+    if node.inner().unwrap().need_expand() {
+        let bigger = node.inner_mut().unwrap().expand();
+        *node = bigger;
+    }
+    let c = leaf.key[depth];
+    node.inner_mut().unwrap().add_child(c, Node::Leaf(leaf));
+}
+
+// subtree of sorted leaves whose first depth bytes are the same, returns it
+// with its number of nodes
+fn build_rec<V>(leaves: &mut [Option<Arc<Leaf<V>>>], depth: usize) -> (Node<V>, usize) {
+    if leaves.len() == 1 {
+        return (Node::Leaf(leaves[0].take().unwrap()), 1);
+    }
+
+    // sorted, so the first and last key share what all of them share
+    let (prefix, d) = {
+        let first = &leaves[0].as_ref().unwrap().key;
+        let last = &leaves[leaves.len() - 1].as_ref().unwrap().key;
+        let p = common_prefix(&first[depth..], &last[depth..]);
+        (first[depth..depth + p].to_vec(), depth + p)
+    };
+
+    // a key ending here is a prefix of the others and comes first
+    let mut start = 0usize;
+    if leaves[0].as_ref().unwrap().key.len() == d {
+        start = 1;
+    }
+    let mut edges = 0usize;
+    let mut last_edge = None;
+    for l in leaves[start..].iter() {
+        let c = l.as_ref().unwrap().key[d];
+        if last_edge != Some(c) {
+            edges += 1;
+            last_edge = Some(c);
+        }
+    }
+
+    let mut node = match edges {
+        0..=4 => Node::N4(Arc::new(Node4::new(&prefix))),
+        5..=16 => Node::N16(Arc::new(Node16::new(&prefix))),
+        17..=48 => Node::N48(Arc::new(Node48::new(&prefix))),
+        _ => Node::N256(Arc::new(Node256::new(&prefix))),
+    };
+    node.inner_mut().unwrap().header_mut().count = leaves.len();
+    let mut size = 1usize;
+    if start == 1 {
+        node.inner_mut().unwrap().header_mut().leaf = leaves[0].take();
+        size += 1;
+    }
+
+    let mut i = start;
+    while i < leaves.len() {
+        let c = leaves[i].as_ref().unwrap().key[d];
+        let mut j = i + 1;
+        while j < leaves.len() && leaves[j].as_ref().unwrap().key[d] == c {
+            j += 1;
+        }
+        let (ch, n) = build_rec(&mut leaves[i..j], d + 1);
+        node.inner_mut().unwrap().add_child(c, ch);
+        size += n;
+        i = j;
+    }
+    (node, size)
+}
+
+// remove key from the subtree of inner node, returns the number of freed nodes
+// and the value, or None when key is not found. An inner node keeps at least two
+// entries (children or its own leaf), so a node left with one is replaced by it.
+fn delete_rec<V>(node: &mut Node<V>, key: &[u8], depth: usize, clone_value: Option<fn(&V) -> V>,
+                 policy: &dyn ResizePolicy) -> Option<(usize, V)> {
+    let mut freed = 1usize;
+    let val;
+    {
+        let n = node.inner_mut().unwrap();
+        if !key[depth..].starts_with(&n.header().prefix) {
+            return None;
+        }
+        let d = depth + n.header().prefix.len();
+
+        if d == key.len() {
+            match n.header_mut().leaf.take() {
+                Some(leaf) => val = leaf_value(leaf, clone_value),
+                None => return None,
+            }
+        } else {
+            let c = key[d];
+            let is_leaf = match n.find_child(c) {
+                Some(Node::Leaf(leaf)) => {
+                    if leaf.key.as_slice() != key {
+                        return None;
+                    }
+                    true
+                },
+                Some(_) => false,
+                None => return None,
+            };
+            if is_leaf {
+                match n.del_child(c) {
+                    Some(Node::Leaf(leaf)) => val = leaf_value(leaf, clone_value),
+                    _ => unreachable!(),
                 }
-                // set parent's child to new node
-                let k = curr_node.borrow_mut().get_edge();
-                let p = curr_node.borrow_mut().get_parent();
-                match p {
-                    Some(ref prc) => prc.borrow_mut().set_child(k, candi),
-                    None => {
-                        println!("Parent node can't be None");
-                        return false;
+            } else {
+                match delete_rec(n.find_child_mut(c).unwrap(), key, d + 1, clone_value, policy) {
+                    Some((n, v)) => {
+                        freed = n;
+                        val = v;
                     },
-                };
+                    None => return None,
+                }
             }
-            // nothing to do
-            break;
         }
-        true
     }
 
-    pub fn get_size(&self) -> usize {
-        self.size
+    node.inner_mut().unwrap().header_mut().count -= 1;
+    let (count, has_leaf) = {
+        let n = node.inner().unwrap();
+        (n.child_count(), n.header().leaf.is_some())
+    };
+
+    // only the leaf of this node is left
+    if count == 0 {
+        let leaf = node.inner_mut().unwrap().header_mut().leaf.take().unwrap();
+        *node = Node::Leaf(leaf);
+        return Some((freed + 1, val));
+    }
+
+    // single child, pull it up with the prefix of this node
+    if count == 1 && !has_leaf {
+        let n = node.inner_mut().unwrap();
+        let c = n.children()[0].0;
+        let mut ch = n.del_child(c).unwrap();
+        if let Some(cn) = ch.inner_mut() {
+            let mut prefix = n.header_mut().prefix.clone();
+            prefix.push(c);
+            prefix.extend_from_slice(&cn.header().prefix);
+            cn.header_mut().prefix = prefix;
+        }
+        *node = ch;
+        return Some((freed + 1, val));
+    }
+
+    if node.should_shrink(policy) {
+        let smaller = node.inner_mut().unwrap().shrink();
+        *node = smaller;
+    }
+    Some((freed, val))
+}
+
+// where the keys of a subtree lie against a range
+enum Cover {
+    Inside,
+    Outside,
+    Partial,
+}
+
+// every key of the subtree starts with path
+fn cover(path: &[u8], lower: &Bound<Vec<u8>>, upper: &Bound<Vec<u8>>) -> Cover {
+    // all keys are above the upper bound, or a lower bound which is not
+    // a key of the subtree is above all of them
+    let outside = match *upper {
+        Bound::Included(ref b) => path > b.as_slice(),
+        Bound::Excluded(ref b) => path >= b.as_slice(),
+        Bound::Unbounded => false,
+    } || match *lower {
+        Bound::Included(ref a) | Bound::Excluded(ref a) => !a.starts_with(path) && a.as_slice() > path,
+        Bound::Unbounded => false,
+    };
+    if outside {
+        return Cover::Outside;
+    }
+    let from = match *lower {
+        Bound::Included(ref a) => a.as_slice() <= path,
+        Bound::Excluded(ref a) => a.as_slice() < path,
+        Bound::Unbounded => true,
+    };
+    let to = match *upper {
+        Bound::Included(ref b) | Bound::Excluded(ref b) => !b.starts_with(path) && b.as_slice() > path,
+        Bound::Unbounded => true,
+    };
+    if from && to { Cover::Inside } else { Cover::Partial }
+}
+
+fn leaf_cover(key: &[u8], lower: &Bound<Vec<u8>>, upper: &Bound<Vec<u8>>) -> Cover {
+    if above_lower(key, lower) && below_upper(key, upper) {
+        Cover::Inside
+    } else {
+        Cover::Outside
+    }
+}
+
+// nodes in a subtree, leaves included
+fn node_count<V>(node: &Node<V>) -> usize {
+    match node.inner() {
+        Some(n) => {
+            let own = 1 + n.header().leaf.is_some() as usize;
+            n.children().iter().fold(own, |sum, &(_, ch)| sum + node_count(ch))
+        },
+        None => 1,
     }
 }
 
-pub struct Node4 {
-    edge: u8,
+// Remove the keys in range from the subtree of inner node, which the range
+// cuts. path holds the key bytes above the node. Children inside the range
+// are detached whole, only children cut by the range are walked into, at
+// most two per level. Returns the removed keys, the freed nodes and whether
+// the node is left empty, then the caller drops it.
+fn delete_range_rec<V>(node: &mut Node<V>, path: &mut Vec<u8>, lower: &Bound<Vec<u8>>,
+                       upper: &Bound<Vec<u8>>, policy: &dyn ResizePolicy) -> (usize, usize, bool) {
+    let mut keys = 0usize;
+    let mut freed = 0usize;
+    {
+        let n = node.inner_mut().unwrap();
+        let depth = path.len();
+        path.extend_from_slice(&n.header().prefix);
+        if n.header().leaf.is_some() && above_lower(path, lower) && below_upper(path, upper) {
+            n.header_mut().leaf = None;
+            keys += 1;
+            freed += 1;
+        }
+
+        let edges: Vec<u8> = n.children().iter().map(|&(c, _)| c).collect();
+        for c in edges {
+            path.push(c);
+            let cover = match *n.find_child(c).unwrap() {
+                Node::Leaf(ref leaf) => leaf_cover(&leaf.key, lower, upper),
+                ref ch => {
+                    let len = path.len();
+                    path.extend_from_slice(&ch.inner().unwrap().header().prefix);
+                    let cover = cover(path, lower, upper);
+                    path.truncate(len);
+                    cover
+                },
+            };
+            match cover {
+                Cover::Inside => {
+                    let ch = n.del_child(c).unwrap();
+                    keys += ch.key_count();
+                    freed += node_count(&ch);
+                },
+                Cover::Outside => (),
+                Cover::Partial => {
+                    let (k, f, empty) = delete_range_rec(n.find_child_mut(c).unwrap(), path, lower, upper, policy);
+                    if empty {
+                        n.del_child(c);
+                    }
+                    keys += k;
+                    freed += f;
+                },
+            }
+            path.pop();
+        }
+        path.truncate(depth);
+        n.header_mut().count -= keys;
+    }
+
+    let (count, has_leaf) = {
+        let n = node.inner().unwrap();
+        (n.child_count(), n.header().leaf.is_some())
+    };
+
+    if count == 0 && !has_leaf {
+        return (keys, freed + 1, true);
+    }
+
+    // only the leaf of this node is left
+    if count == 0 {
+        let leaf = node.inner_mut().unwrap().header_mut().leaf.take().unwrap();
+        *node = Node::Leaf(leaf);
+        return (keys, freed + 1, false);
+    }
+
+    // single child, pull it up with the prefix of this node
+    if count == 1 && !has_leaf {
+        let n = node.inner_mut().unwrap();
+        let c = n.children()[0].0;
+        let mut ch = n.del_child(c).unwrap();
+        if let Some(cn) = ch.inner_mut() {
+            let mut prefix = n.header_mut().prefix.clone();
+            prefix.push(c);
+            prefix.extend_from_slice(&cn.header().prefix);
+            cn.header_mut().prefix = prefix;
+        }
+        *node = ch;
+        return (keys, freed + 1, false);
+    }
+
+    // many children may be gone, shrink until the node fits
+    while node.should_shrink(policy) {
+        let smaller = node.inner_mut().unwrap().shrink();
+        *node = smaller;
+    }
+    (keys, freed, false)
+}
+
+fn leaf_digest<V: Hash>(leaf: &Leaf<V>) -> u64 {
+    let mut h = DefaultHasher::new();
+    leaf.key.hash(&mut h);
+    leaf.value.hash(&mut h);
+    h.finish()
+}
+
+// hash of the prefix, the own leaf and the edges and digests of the
+// children, kept in the header until the node changes
+fn node_digest<V: Hash>(node: &Node<V>) -> u64 {
+    let n = match node.inner() {
+        Some(n) => n,
+        None => match *node {
+            Node::Leaf(ref leaf) => return leaf_digest(leaf),
+            _ => unreachable!(),
+        },
+    };
+    let hdr = n.header();
+    let known = hdr.digest.load(Ordering::Relaxed);
+    if known != 0 {
+        return known;
+    }
+    let mut h = DefaultHasher::new();
+    hdr.prefix.hash(&mut h);
+    hdr.leaf.as_ref().map(|leaf| leaf_digest(leaf)).hash(&mut h);
+    for (c, ch) in n.children() {
+        c.hash(&mut h);
+        node_digest(ch).hash(&mut h);
+    }
+    // 0 stands for unknown
+    let d = h.finish().max(1);
+    hdr.digest.store(d, Ordering::Relaxed);
+    d
+}
+
+// a subtree in the walk of diff with the whole key path to it
+struct Side<'a, V> {
+    node: &'a Node<V>,
+    path: Vec<u8>,
+}
+
+impl<'a, V> Side<'a, V> {
+    fn new(node: &'a Node<V>, above: &[u8]) -> Side<'a, V> {
+        let path = match *node {
+            Node::Leaf(ref leaf) => leaf.key.clone(),
+            _ => {
+                let mut path = above.to_vec();
+                path.extend_from_slice(&node.inner().unwrap().header().prefix);
+                path
+            },
+        };
+        Side { node, path }
+    }
+
+    // the key equal to the path walked so far
+    fn leaf_at(&self, walked: &[u8]) -> Option<&'a Leaf<V>> {
+        if self.path.len() != walked.len() {
+            return None;
+        }
+        match *self.node {
+            Node::Leaf(ref leaf) => Some(leaf),
+            _ => self.node.inner().unwrap().header().leaf.as_deref(),
+        }
+    }
+
+    // next bytes of the keys below the path walked so far
+    fn edges(&self, walked: &[u8]) -> Vec<u8> {
+        if self.path.len() > walked.len() {
+            return vec![self.path[walked.len()]];
+        }
+        match self.node.inner() {
+            Some(n) => n.children().iter().map(|&(c, _)| c).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // the subtree of the keys starting with walked and c
+    fn below(&self, walked: &[u8], c: u8) -> Option<Side<'a, V>> {
+        if self.path.len() > walked.len() {
+            if self.path[walked.len()] != c {
+                return None;
+            }
+            return Some(Side {
+                node: self.node,
+                path: self.path.clone(),
+            });
+        }
+        let mut above = walked.to_vec();
+        above.push(c);
+        self.node.find_child(c).map(|ch| Side::new(ch, &above))
+    }
+}
+
+// keys differing between a and b, which hold the keys of their trees starting
+// with walked; the walk goes one byte down at a time
+fn diff_rec<V: Hash>(walked: &mut Vec<u8>, a: Option<Side<V>>, b: Option<Side<V>>,
+                     keys: &mut Vec<Vec<u8>>) {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        (Some(s), None) | (None, Some(s)) => {
+            subtree_keys(s.node, keys);
+            return;
+        },
+        (None, None) => return,
+    };
+    if a.path == b.path && node_digest(a.node) == node_digest(b.node) {
+        return;
+    }
+
+    let differ = match (a.leaf_at(walked), b.leaf_at(walked)) {
+        (Some(x), Some(y)) => leaf_digest(x) != leaf_digest(y),
+        (None, None) => false,
+        _ => true,
+    };
+    if differ {
+        keys.push(walked.clone());
+    }
+
+    let mut edges = a.edges(walked);
+    edges.extend(b.edges(walked));
+    edges.sort();
+    edges.dedup();
+    for c in edges {
+        let (x, y) = (a.below(walked, c), b.below(walked, c));
+        walked.push(c);
+        diff_rec(walked, x, y, keys);
+        walked.pop();
+    }
+}
+
+// all keys of a subtree, in byte order
+fn subtree_keys<V>(node: &Node<V>, keys: &mut Vec<Vec<u8>>) {
+    match node.inner() {
+        Some(n) => {
+            if let Some(ref leaf) = n.header().leaf {
+                keys.push(leaf.key.clone());
+            }
+            for (_, ch) in n.children() {
+                subtree_keys(ch, keys);
+            }
+        },
+        None => match *node {
+            Node::Leaf(ref leaf) => keys.push(leaf.key.clone()),
+            _ => unreachable!(),
+        },
+    }
+}
+
+fn node_leaf_key<V>(node: &Node<V>) -> &[u8] {
+    match *node {
+        Node::Leaf(ref leaf) => &leaf.key,
+        _ => unreachable!(),
+    }
+}
+
+// bytes as text, the printable ones as they are and the others in hex
+fn bytes_text(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in bytes {
+        match b {
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    s
+}
+
+// lines of a label, escaped for a quoted DOT string
+fn dot_label(lines: &[String]) -> String {
+    let lines: Vec<String> = lines.iter().map(|l| l.replace('\\', "\\\\").replace('"', "\\\"")).collect();
+    lines.join("\\n")
+}
+
+fn leaf_text<V: fmt::Debug>(leaf: &Leaf<V>) -> String {
+    format!("{} = {:?}", bytes_text(&leaf.key), leaf.value)
+}
+
+// write node and its subtree with ids from next on, returns the id of node
+fn dot_rec<V: fmt::Debug, W: Write>(w: &mut W, node: &Node<V>, depth: usize,
+                                    max_depth: Option<usize>, next: &mut usize) -> io::Result<usize> {
+    let id = *next;
+    *next += 1;
+    let (kind, n) = match *node {
+        Node::Leaf(ref leaf) => {
+            writeln!(w, "    n{} [shape=ellipse, label=\"{}\"];", id, dot_label(&[leaf_text(leaf)]))?;
+            return Ok(id);
+        },
+        Node::N4(ref n) => ("Node4", &**n as &dyn ArtNode<V>),
+        Node::N16(ref n) => ("Node16", &**n as &dyn ArtNode<V>),
+        Node::N48(ref n) => ("Node48", &**n as &dyn ArtNode<V>),
+        Node::N256(ref n) => ("Node256", &**n as &dyn ArtNode<V>),
+    };
+    let hdr = n.header();
+    let mut lines = vec![
+        kind.to_string(),
+        format!("prefix \"{}\"", bytes_text(&hdr.prefix)),
+        format!("{} keys", hdr.count),
+    ];
+    if let Some(ref leaf) = hdr.leaf {
+        lines.push(leaf_text(leaf));
+    }
+    let label = dot_label(&lines);
+    writeln!(w, "    n{} [label=\"{}\"];", id, label)?;
+
+    if max_depth.is_some_and(|max| depth >= max) {
+        writeln!(w, "    n{} [shape=plaintext, label=\"...\"];", *next)?;
+        writeln!(w, "    n{} -> n{};", id, *next)?;
+        *next += 1;
+        return Ok(id);
+    }
+    for (c, ch) in n.children() {
+        let ch_id = dot_rec(w, ch, depth + 1, max_depth, next)?;
+        writeln!(w, "    n{} -> n{} [label=\"{}\"];", id, ch_id, dot_label(&[bytes_text(&[c])]))?;
+    }
+    Ok(id)
+}
+
+fn leaf_memory<V>(leaf: &Leaf<V>) -> usize {
+    2 * mem::size_of::<usize>() + mem::size_of::<Leaf<V>>() + leaf.key.capacity()
+}
+
+// check the subtree of node below the key bytes in path, returns its number
+// of keys and nodes
+fn validate_rec<V>(node: &Node<V>, path: &mut Vec<u8>,
+                   policy: &dyn ResizePolicy) -> Result<(usize, usize), InvalidTree> {
+    let invalid = |path: &Vec<u8>, problem| Err(InvalidTree {
+        path: path.clone(),
+        problem,
+    });
+    let n = match node.inner() {
+        Some(n) => n,
+        None => match *node {
+            Node::Leaf(ref leaf) => {
+                if !leaf.key.starts_with(path) {
+                    return invalid(path, "leaf key does not start with its path");
+                }
+                return Ok((1, 1));
+            },
+            _ => unreachable!(),
+        },
+    };
+
+    if let Err(problem) = n.check() {
+        return invalid(path, problem);
+    }
+    let depth = path.len();
+    path.extend_from_slice(&n.header().prefix);
+    let entries = n.child_count() + n.header().leaf.is_some() as usize;
+    if entries < 2 {
+        return invalid(path, "inner node with less than two entries");
+    }
+    if node.should_shrink(policy) {
+        return invalid(path, "node under its shrink threshold");
+    }
+    let mut keys = 0usize;
+    let mut nodes = 1usize;
+    if let Some(ref leaf) = n.header().leaf {
+        if leaf.key != *path {
+            return invalid(path, "own leaf key is not the node path");
+        }
+        keys += 1;
+        nodes += 1;
+    }
+    let children = n.children();
+    if children.len() != n.child_count() {
+        return invalid(path, "child count does not match the children");
+    }
+    for (c, ch) in children {
+        if n.find_child(c).map(|f| f as *const Node<V>) != Some(ch as *const Node<V>) {
+            return invalid(path, "edge does not find its child");
+        }
+        path.push(c);
+        let (k, c) = validate_rec(ch, path, policy)?;
+        path.pop();
+        keys += k;
+        nodes += c;
+    }
+    if keys != n.header().count {
+        return invalid(path, "count is not the number of keys below");
+    }
+    path.truncate(depth);
+    Ok((keys, nodes))
+}
+
+pub struct Leaf<V> {
+    key: Vec<u8>,
+    value: V,
+}
+
+// the leaf to change the value of, copied first when a snapshot shares it;
+// a leaf is only shared after snapshot() set clone_value
+fn leaf_mut<V>(leaf: &mut Arc<Leaf<V>>, clone_value: Option<fn(&V) -> V>) -> &mut Leaf<V> {
+    if Arc::get_mut(leaf).is_none() {
+        let value = clone_value.unwrap()(&leaf.value);
+        *leaf = Arc::new(Leaf {
+            key: leaf.key.clone(),
+            value,
+        });
+    }
+    Arc::get_mut(leaf).unwrap()
+}
+
+// Where the value of a leaf in the tree is, the leaf is copied first when
+// it is shared. The leaf is on the heap behind its Arc, so moving the Arc
+// between nodes, as expand and put_leaf do, keeps the pointer good. It is
+// good until the Arc is cloned or dropped, which the rest of entry_rec and
+// entry_inner never do: the nodes above were made unique by inner_mut on the
+// way down, so inner_mut on them again copies nothing.
+fn value_ptr<V>(leaf: &mut Arc<Leaf<V>>, clone_value: Option<fn(&V) -> V>) -> *mut V {
+    &mut leaf_mut(leaf, clone_value).value
+}
+
+// the value of a leaf taken out of the tree
+fn leaf_value<V>(leaf: Arc<Leaf<V>>, clone_value: Option<fn(&V) -> V>) -> V {
+    match Arc::try_unwrap(leaf) {
+        Ok(leaf) => leaf.value,
+        Err(leaf) => clone_value.unwrap()(&leaf.value),
+    }
+}
+
+impl<V> Leaf<V> {
+    pub fn new(key: &[u8], val: V) -> Arc<Leaf<V>> {
+        Arc::new(Leaf {
+            key: key.to_vec(),
+            value: val,
+        })
+    }
+}
+
+pub struct Node4<V> {
+    header: Header<V>,
+    len: u8,
     keys: [u8; 4],
-    children: Vec<Option<Rc<RefCell<dyn ArtNode>>>>,
-    parent: Option<Rc<RefCell<dyn ArtNode>>>,
-    values: Vec<String>,
+    children: [Option<Node<V>>; 4],
 }
 
-pub struct Node16 {
-    edge: u8,
+pub struct Node16<V> {
+    header: Header<V>,
+    len: u8,
     keys: [u8; 16],
-    children: Vec<Option<Rc<RefCell<dyn ArtNode>>>>,
-    parent: Option<Rc<RefCell<dyn ArtNode>>>,
-    values: Vec<String>,
+    children: [Option<Node<V>>; 16],
 }
 
-pub struct Node48 {
-    edge: u8,
+pub struct Node48<V> {
+    header: Header<V>,
+    len: u8,
+    // slot of the child in children, -1 if none
     keys: [i8; 256],
-    children: Vec<Option<Rc<RefCell<dyn ArtNode>>>>,
-    parent: Option<Rc<RefCell<dyn ArtNode>>>,
-    values: Vec<String>,
+    children: [Option<Node<V>>; 48],
 }
 
-pub struct Node256 {
-    edge: u8,
-    children: Vec<Option<Rc<RefCell<dyn ArtNode>>>>,
-    parent: Option<Rc<RefCell<dyn ArtNode>>>,
-    values: Vec<String>,
+pub struct Node256<V> {
+    header: Header<V>,
+    len: u16,
+    children: [Option<Node<V>>; 256],
 }
 
-impl Node4 {
-    pub fn new(edge: u8) -> Node4 {
-        let mut node = Node4 {
-            edge: edge,
+impl<V> Node4<V> {
+    pub fn new(prefix: &[u8]) -> Node4<V> {
+        Node4 {
+            header: Header::new(prefix),
+            len: 0,
             keys: [0; 4],
-            children: Vec::new(),
-            parent: None,
-            values: Vec::new(),
-        };
-        node.children.resize_with(4, || { None });
-        node
+            children: Default::default(),
+        }
     }
 }
 
-impl Node16 {
-    pub fn new(edge: u8) -> Node16 {
-        let mut node = Node16 {
-            edge: edge,
+// slot of key in the first len of 16 sorted keys: compare all 16 keys at
+// once and take the first match from the movemask, slots past len are
+// masked off
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+pub fn find_key16(keys: &[u8; 16], len: usize, key: u8) -> Option<usize> {
+    use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8};
+
+    let mask = unsafe {
+        let k = _mm_set1_epi8(key as i8);
+        let ks = _mm_loadu_si128(keys.as_ptr() as *const __m128i);
+        _mm_movemask_epi8(_mm_cmpeq_epi8(k, ks)) as u32
+    };
+    let mask = mask & ((1u32 << len) - 1);
+    if mask == 0 {
+        return None;
+    }
+    Some(mask.trailing_zeros() as usize)
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+pub fn find_key16(keys: &[u8; 16], len: usize, key: u8) -> Option<usize> {
+    keys[..len].binary_search(&key).ok()
+}
+
+impl<V> Node16<V> {
+    pub fn new(prefix: &[u8]) -> Node16<V> {
+        Node16 {
+            header: Header::new(prefix),
+            len: 0,
             keys: [0; 16],
-            children: Vec::new(),
-            parent: None,
-            values: Vec::new(),
-        };
-        node.children.resize_with(16, || { None });
-        node
+            children: Default::default(),
+        }
+    }
+
+    pub fn find_index(&self, key: u8) -> Option<usize> {
+        find_key16(&self.keys, self.len as usize, key)
+    }
+
+    // portable lookup, keys are sorted
+    pub fn find_index_binary(&self, key: u8) -> Option<usize> {
+        self.keys[..self.len as usize].binary_search(&key).ok()
+    }
+
+    // plain loop over the keys, kept to compare with in the benchmark
+    pub fn find_index_linear(&self, key: u8) -> Option<usize> {
+        self.keys[..self.len as usize].iter().position(|&k| k == key)
     }
 }
 
-impl Node48 {
-    pub fn new(edge: u8) -> Node48 {
-        let node = Node48 {
-            edge: edge,
+impl<V> Node48<V> {
+    pub fn new(prefix: &[u8]) -> Node48<V> {
+        Node48 {
+            header: Header::new(prefix),
+            len: 0,
             keys: [-1; 256],
-            children: Vec::with_capacity(48),
-            parent: None,
-            values: Vec::new(),
-        };
-        node
+            children: std::array::from_fn(|_| None),
+        }
     }
 }
 
-impl Node256 {
-    pub fn new(edge: u8) -> Node256 {
-        let mut node = Node256 {
-            edge: edge,
-            children: Vec::new(),
-            parent: None,
-            values: Vec::new(),
-        };
-        node.children.resize_with(256, || { None });
-        node
+impl<V> Node256<V> {
+    pub fn new(prefix: &[u8]) -> Node256<V> {
+        Node256 {
+            header: Header::new(prefix),
+            len: 0,
+            children: std::array::from_fn(|_| None),
+        }
     }
 }
 
-impl ArtNode for Node4 {
+impl<V> Clone for Node4<V> {
+    fn clone(&self) -> Node4<V> {
+        Node4 {
+            header: self.header.clone(),
+            len: self.len,
+            keys: self.keys,
+            children: self.children.clone(),
+        }
+    }
+}
 
-    // Node4
-    fn get_child(&mut self, key: u8) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut idx: u8 = u8::MAX;
-        for i in 0..self.keys.len() {
-            if self.keys[i] == key {
-                idx = i as u8;
-                break;
-            }
+impl<V> Clone for Node16<V> {
+    fn clone(&self) -> Node16<V> {
+        Node16 {
+            header: self.header.clone(),
+            len: self.len,
+            keys: self.keys,
+            children: self.children.clone(),
         }
-        if idx == u8::MAX {
-            return None; 
+    }
+}
+
+impl<V> Clone for Node48<V> {
+    fn clone(&self) -> Node48<V> {
+        Node48 {
+            header: self.header.clone(),
+            len: self.len,
+            keys: self.keys,
+            children: self.children.clone(),
         }
-        match self.children[idx as usize] {
-            Some(ref rc) => Some(rc.clone()),
-            None => None,
+    }
+}
+
+impl<V> Clone for Node256<V> {
+    fn clone(&self) -> Node256<V> {
+        Node256 {
+            header: self.header.clone(),
+            len: self.len,
+            children: self.children.clone(),
         }
     }
+}
+
+impl<V> ArtNode<V> for Node4<V> {
+
+    // Node4
+    fn header(&self) -> &Header<V> {
+        &self.header
+    }
 
     // Node4
-    // ch's parent node should be set already
-    fn set_child(&mut self, key: u8, ch: Option<Rc<RefCell<dyn ArtNode>>>) -> bool {
-        for i in 0..4 {
+    fn header_mut(&mut self) -> &mut Header<V> {
+        &mut self.header
+    }
+
+    // Node4
+    fn find_child(&self, key: u8) -> Option<&Node<V>> {
+        for i in 0..self.len as usize {
             if self.keys[i] == key {
-                match self.children[i] {
-                    Some(ref rc) => {
-                        rc.borrow_mut().set_parent(None);
-                    },
-                    None => {},
-                }
-                self.children[i] = ch;
-                self.keys[i] = key;
-                return true;
+                return self.children[i].as_ref();
             }
         }
-        
-        let mut idx: usize = 0;
-        let mut has_slot = false;
-        for i in 0..4 {
-            if self.children[i].is_none() {
-                idx = i;
-                has_slot = true;
-                self.children[i] = ch;
-                break;
+        None
+    }
+
+    // Node4
+    fn find_child_mut(&mut self, key: u8) -> Option<&mut Node<V>> {
+        for i in 0..self.len as usize {
+            if self.keys[i] == key {
+                return self.children[i].as_mut();
             }
         }
-        if has_slot == false {
-            return false;
+        None
+    }
+
+    // Node4
+    // keys are kept sorted, children after key move one slot right
+    fn add_child(&mut self, key: u8, ch: Node<V>) {
+        let mut idx = self.len as usize;
+        while idx > 0 && self.keys[idx - 1] > key {
+            self.keys[idx] = self.keys[idx - 1];
+            self.children[idx] = self.children[idx - 1].take();
+            idx -= 1;
         }
         self.keys[idx] = key;
-        true
+        self.children[idx] = Some(ch);
+        self.len += 1;
     }
-    
+
     // Node4
-    fn del_child(&mut self, key: u8) {
-        for i in 0..4 {
+    fn del_child(&mut self, key: u8) -> Option<Node<V>> {
+        for i in 0..self.len as usize {
             if self.keys[i] == key {
-                match self.children[i] {
-                    Some(ref rc) => {
-                        rc.borrow_mut().set_parent(None);
-                    },
-                    None => {},
+                let ch = self.children[i].take();
+                for j in i + 1..self.len as usize {
+                    self.keys[j - 1] = self.keys[j];
+                    self.children[j - 1] = self.children[j].take();
                 }
-                self.children[i] = None;
+                self.len -= 1;
+                self.header.deletes = self.header.deletes.saturating_add(1);
+                return ch;
             }
         }
+        None
     }
 
     // Node4
-    fn get_children(&mut self) -> Vec<Option<Rc<RefCell<dyn ArtNode>>>> {
-        let mut v: Vec<Option<Rc<RefCell<dyn ArtNode>>>> = Vec::new();
-        v.clone_from(&self.children);
+    fn children(&self) -> Vec<(u8, &Node<V>)> {
+        let mut v = Vec::with_capacity(self.len as usize);
+        for i in 0..self.len as usize {
+            v.push((self.keys[i], self.children[i].as_ref().unwrap()));
+        }
         v
     }
-    
+
     // Node4
-    fn empty(&self) -> bool {
-        for i in 0..4 {
-            if self.children[i].is_some() {
-                return false;
-            }
-        }
-        true
+    fn child_count(&self) -> usize {
+        self.len as usize
     }
 
     // Node4
-    fn expand(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut node16 = Node16::new(self.edge);
-        
-        // copy values to new node
-        node16.values.clone_from(&mut self.values);
-
-        // copy children to new node
-        for i in 0..4 {
-            match self.children[i] {
-                Some(ref rc) => node16.children[i] = Some(rc.clone()),
-                None => {},
+    fn child_at_or_after(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for i in 0..self.len as usize {
+            if self.keys[i] >= key {
+                return Some((self.keys[i], self.children[i].as_ref().unwrap()));
             }
-            node16.keys[i] = self.keys[i];
-        }
-       
-        // set parent for new node
-        match self.parent {
-            Some(ref rc) => node16.parent = Some(rc.clone()),
-            None => {
-                println!("Parent node can't be None");
-                return None;
-            },
         }
-        let rc = Rc::new(RefCell::new(node16));
+        None
+    }
 
-        // set children's parent to new node
-        let chs = rc.borrow_mut().get_children();
-        for cn in chs.iter() {
-            match *cn {
-                Some(ref crc) => crc.borrow_mut().set_parent(Some(rc.clone())),
-                None => {},
+    // Node4
+    fn child_at_or_before(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for i in (0..self.len as usize).rev() {
+            if self.keys[i] <= key {
+                return Some((self.keys[i], self.children[i].as_ref().unwrap()));
             }
         }
-        Some(rc.clone())
-    }
-    
-    // Node4
-    fn get_edge(&self) -> u8 {
-        self.edge
+        None
     }
-    
+
     // Node4
-    fn need_expand(&self) -> bool {
-        let mut full = true;
-        for i in 0..4 {
-            if self.children[i].is_none() {
-                full = false;
+    fn keys_before(&self, key: u8) -> usize {
+        let mut n = 0usize;
+        for i in 0..self.len as usize {
+            if self.keys[i] >= key {
                 break;
             }
+            n += self.children[i].as_ref().unwrap().key_count();
         }
-        full
-    }
-    
-    // Node4
-    fn need_shrink(&self) -> bool {
-        false
+        n
     }
-    
+
     // Node4
-    fn set_value(&mut self, val: String) {
-        self.values.push(val);
+    fn check(&self) -> Result<(), &'static str> {
+        let len = self.len as usize;
+        if len > 4 {
+            return Err("more children than slots");
+        }
+        if !self.keys[..len].windows(2).all(|w| w[0] < w[1]) {
+            return Err("keys not sorted");
+        }
+        if self.children.iter().enumerate().any(|(i, ch)| ch.is_some() != (i < len)) {
+            return Err("children do not match len");
+        }
+        Ok(())
     }
-    
+
     // Node4
-    fn get_values(&self) -> Option<Vec<String>> {
-        let mut r: Vec<String> = Vec::new();
-        r.clone_from(&self.values);
-        Some(r)
+    fn need_expand(&self) -> bool {
+        self.len == 4
     }
-    
+
     // Node4
-    fn del_values(&mut self) {
-        self.values.clear();
+    fn need_shrink(&self, _: &dyn ResizePolicy) -> bool {
+        false
     }
-    
+
     // Node4
-    fn set_parent(&mut self, pare: Option<Rc<RefCell<dyn ArtNode>>>) {
-        self.parent = pare;
+    fn expand(&mut self) -> Node<V> {
+        let mut node16 = Node16::new(&[]);
+        node16.header = self.header.take();
+
+        // move children to new node
+        for i in 0..self.len as usize {
+            node16.keys[i] = self.keys[i];
+            node16.children[i] = self.children[i].take();
+        }
+        node16.len = self.len;
+        self.len = 0;
+        Node::N16(Arc::new(node16))
     }
-    
+
     // Node4
-    fn get_parent(&self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        match self.parent {
-            Some(ref rc) => return Some(rc.clone()),
-            None => return None,
-        }
+    fn shrink(&mut self) -> Node<V> {
+        unreachable!("Node4 can't shrink")
     }
 }
 
-impl ArtNode for Node16 {
+impl<V> ArtNode<V> for Node16<V> {
 
     // Node16
-    fn get_child(&mut self, key: u8) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut idx: u8 = u8::MAX;
-        for i in 0..self.keys.len() {
-            if self.keys[i] == key {
-                idx = i as u8;
-                break;
-            }
-        }
-        if idx == u8::MAX {
-            return None; 
-        }
-        match self.children[idx as usize] {
-            Some(ref rc) => Some(rc.clone()),
+    fn header(&self) -> &Header<V> {
+        &self.header
+    }
+
+    // Node16
+    fn header_mut(&mut self) -> &mut Header<V> {
+        &mut self.header
+    }
+
+    // Node16
+    fn find_child(&self, key: u8) -> Option<&Node<V>> {
+        match self.find_index(key) {
+            Some(i) => self.children[i].as_ref(),
             None => None,
         }
     }
-    
+
     // Node16
-    fn set_child(&mut self, key: u8, ch: Option<Rc<RefCell<dyn ArtNode>>>) -> bool {
-        for i in 0..16 {
-            if self.keys[i] == key {
-                match self.children[i] {
-                    Some(ref rc) => {
-                        rc.borrow_mut().set_parent(None);
-                    },
-                    None => {},
-                }
-                self.children[i] = ch;
-                self.keys[i] = key;
-                return true;
-            }
-        }
-        
-        let mut idx: usize = 0;
-        let mut has_slot = false;
-        for i in 0..16 {
-            if self.children[i].is_none() {
-                idx = i;
-                has_slot = true;
-                self.children[i] = ch;
-                break;
-            }
-        }
-        if has_slot == false {
-            return false;
+    fn find_child_mut(&mut self, key: u8) -> Option<&mut Node<V>> {
+        match self.find_index(key) {
+            Some(i) => self.children[i].as_mut(),
+            None => None,
         }
-        self.keys[idx] = key;
-        true
     }
-    
+
     // Node16
-    fn del_child(&mut self, key: u8) {
-        for i in 0..16 {
-            if self.keys[i] == key {
-                match self.children[i] {
-                    Some(ref rc) => {
-                        rc.borrow_mut().set_parent(None);
-                    },
-                    None => {},
-                }
-                self.children[i] = None;
-            }
+    // keys are kept sorted, children after key move one slot right
+    fn add_child(&mut self, key: u8, ch: Node<V>) {
+        let mut idx = self.len as usize;
+        while idx > 0 && self.keys[idx - 1] > key {
+            self.keys[idx] = self.keys[idx - 1];
+            self.children[idx] = self.children[idx - 1].take();
+            idx -= 1;
         }
+        self.keys[idx] = key;
+        self.children[idx] = Some(ch);
+        self.len += 1;
     }
-    
+
     // Node16
-    fn empty(&self) -> bool {
-        for i in 0..16 {
-            if self.children[i].is_some() {
-                return false;
-            }
+    fn del_child(&mut self, key: u8) -> Option<Node<V>> {
+        let i = self.find_index(key)?;
+        let ch = self.children[i].take();
+        for j in i + 1..self.len as usize {
+            self.keys[j - 1] = self.keys[j];
+            self.children[j - 1] = self.children[j].take();
         }
-        true 
+        self.len -= 1;
+        self.header.deletes = self.header.deletes.saturating_add(1);
+        ch
     }
-    
+
     // Node16
-    fn get_children(&mut self) -> Vec<Option<Rc<RefCell<dyn ArtNode>>>> {
-        let mut v: Vec<Option<Rc<RefCell<dyn ArtNode>>>> = Vec::new();
-        v.clone_from(&self.children);
+    fn children(&self) -> Vec<(u8, &Node<V>)> {
+        let mut v = Vec::with_capacity(self.len as usize);
+        for i in 0..self.len as usize {
+            v.push((self.keys[i], self.children[i].as_ref().unwrap()));
+        }
         v
     }
 
     // Node16
-    fn shrink(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut node4 = Node4::new(self.edge);
-        let mut csize = 0usize;
-        
-        // copy values to new node
-        node4.values.clone_from(&mut self.values);
-        
-        // copy children to new node
-        for i in 0..16 {
-            match self.children[i] {
-                Some(ref rc) => {
-                    node4.children[csize] = Some(rc.clone());
-                    node4.keys[csize] = self.keys[i];
-                    csize += 1;
-                },
-                None => {},
-            }
-            if csize >= 4 {
-                break;
-            }
-        }
+    fn child_count(&self) -> usize {
+        self.len as usize
+    }
 
-        // set parent for new node
-        match self.parent {
-            Some(ref rc) => node4.parent = Some(rc.clone()),
-            None => {
-                println!("Parent node can't be None");
-                return None;
-            }
-        }
-        let rc = Rc::new(RefCell::new(node4));
-        
-        // set children's parent to new node
-        let chs = rc.borrow_mut().get_children();
-        for cn in chs.iter() {
-            match *cn {
-                Some(ref crc) => crc.borrow_mut().set_parent(Some(rc.clone())),
-                None => {},
+    // Node16
+    fn child_at_or_after(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for i in 0..self.len as usize {
+            if self.keys[i] >= key {
+                return Some((self.keys[i], self.children[i].as_ref().unwrap()));
             }
         }
-        Some(rc.clone())
+        None
     }
 
     // Node16
-    fn expand(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut node48 = Node48::new(self.edge);
-        
-        // copy values to new node
-        node48.values.clone_from(&mut self.values);
-
-        // copy children to new node
-        for i in 0..16 {
-            match self.children[i] {
-                Some(ref rc) => {
-                    let key = rc.borrow().get_edge();
-                    node48.children.push(Some(rc.clone()));
-                    node48.keys[key as usize] = (node48.children.len()-1) as i8;
-                },
-                None => {},
-            }
-        }
-       
-        // set parent for new node
-        match self.parent {
-            Some(ref rc) => node48.parent = Some(rc.clone()),
-            None => {
-                println!("Parent node can't be None");
-                return None;
-            }
-        }
-        let rc = Rc::new(RefCell::new(node48));
-      
-        // set children's parent to new node
-        let chs = rc.borrow_mut().get_children();
-        for cn in chs.iter() {
-            match *cn {
-                Some(ref crc) => crc.borrow_mut().set_parent(Some(rc.clone())),
-                None => {},
+    fn child_at_or_before(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for i in (0..self.len as usize).rev() {
+            if self.keys[i] <= key {
+                return Some((self.keys[i], self.children[i].as_ref().unwrap()));
             }
         }
-        Some(rc.clone())
-    }
-    
-    // Node16
-    fn get_edge(&self) -> u8 {
-        self.edge
+        None
     }
-    
+
     // Node16
-    fn need_expand(&self) -> bool {
-        let mut full = true; 
-        for i in 0..16 {
-            if self.children[i].is_none() {
-                full = false;
+    fn keys_before(&self, key: u8) -> usize {
+        let mut n = 0usize;
+        for i in 0..self.len as usize {
+            if self.keys[i] >= key {
                 break;
             }
+            n += self.children[i].as_ref().unwrap().key_count();
         }
-        full
+        n
     }
-    
+
     // Node16
-    fn need_shrink(&self) -> bool {
-        let mut count = 0i32;
-        for i in 0..16 {
-            if self.children[i].is_some() {
-                count += 1;
-            }
+    fn check(&self) -> Result<(), &'static str> {
+        let len = self.len as usize;
+        if len > 16 {
+            return Err("more children than slots");
         }
-        if count < 3 {
-            return true;
+        if !self.keys[..len].windows(2).all(|w| w[0] < w[1]) {
+            return Err("keys not sorted");
         }
-        false
-    }
-    
-    // Node16
-    fn set_value(&mut self, val: String) {
-        self.values.push(val);
+        if self.children.iter().enumerate().any(|(i, ch)| ch.is_some() != (i < len)) {
+            return Err("children do not match len");
+        }
+        Ok(())
     }
-    
+
     // Node16
-    fn get_values(&self) -> Option<Vec<String>> {
-        let mut r: Vec<String> = Vec::new();
-        r.clone_from(&self.values);
-        Some(r)
+    fn need_expand(&self) -> bool {
+        self.len == 16
     }
-    
+
     // Node16
-    fn del_values(&mut self) {
-        self.values.clear();
+    fn need_shrink(&self, policy: &dyn ResizePolicy) -> bool {
+        policy.shrink(NodeKind::N16, self.len as usize, self.header.deletes as usize)
     }
-    
+
     // Node16
-    fn set_parent(&mut self, pare: Option<Rc<RefCell<dyn ArtNode>>>) {
-        self.parent = pare;
+    fn expand(&mut self) -> Node<V> {
+        let mut node48 = Node48::new(&[]);
+        node48.header = self.header.take();
+
+        // move children to new node
+        for i in 0..self.len as usize {
+            node48.keys[self.keys[i] as usize] = i as i8;
+            node48.children[i] = self.children[i].take();
+        }
+        node48.len = self.len;
+        self.len = 0;
+        Node::N48(Arc::new(node48))
     }
-    
+
     // Node16
-    fn get_parent(&self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        match self.parent {
-            Some(ref rc) => return Some(rc.clone()),
-            None => return None,
+    fn shrink(&mut self) -> Node<V> {
+        let mut node4 = Node4::new(&[]);
+        node4.header = self.header.take();
+
+        // move children to new node
+        for i in 0..self.len as usize {
+            node4.keys[i] = self.keys[i];
+            node4.children[i] = self.children[i].take();
         }
+        node4.len = self.len;
+        self.len = 0;
+        Node::N4(Arc::new(node4))
     }
 }
 
-impl ArtNode for Node48 {
-    
+impl<V> ArtNode<V> for Node48<V> {
+
     // Node48
-    fn set_child(&mut self, key: u8, ch: Option<Rc<RefCell<dyn ArtNode>>>) -> bool {
-        let mut idx = self.keys[key as usize];
-        if idx >= 0 {
-            self.children[idx as usize] = ch;
-            return true;
-        }
+    fn header(&self) -> &Header<V> {
+        &self.header
+    }
 
-        for i in 0..48 {
-            if self.children[i].is_none() {
-                idx = i as i8;
-                self.children[i] = ch;
-                break;
-            }
-        }
-        // no more place to insert node
-        if idx < 0 {
-            return false;
-        } 
-        self.keys[key as usize] = idx; 
-        true
+    // Node48
+    fn header_mut(&mut self) -> &mut Header<V> {
+        &mut self.header
     }
 
     // Node48
-    fn del_child(&mut self, key: u8) {
+    fn find_child(&self, key: u8) -> Option<&Node<V>> {
         let idx = self.keys[key as usize];
-        if idx >= 0 {
-            match self.children[idx as usize] {
-                Some(ref rc) => rc.borrow_mut().set_parent(None),
-                None => {},
-            }
-            self.children[idx as usize] = None;
-            self.keys[key as usize] = -1;
+        if idx < 0 {
+            return None;
         }
+        self.children[idx as usize].as_ref()
     }
 
     // Node48
-    fn get_child(&mut self, key: u8) -> Option<Rc<RefCell<dyn ArtNode>>> {
+    fn find_child_mut(&mut self, key: u8) -> Option<&mut Node<V>> {
         let idx = self.keys[key as usize];
         if idx < 0 {
             return None;
         }
-        match self.children[idx as usize] {
-            Some(ref rc) => {
-                // wrong pointer
-                if rc.borrow().get_edge() != key {
-                    self.keys[key as usize] = -1;
-                    return None;
-                }
-                return Some(rc.clone());
-            },
-            // cleaning index
-            None => self.keys[key as usize] = -1,
-        }
-        None
+        self.children[idx as usize].as_mut()
     }
-    
+
     // Node48
-    fn empty(&self) -> bool {
+    fn add_child(&mut self, key: u8, ch: Node<V>) {
         for i in 0..48 {
-            if self.keys[i] >= 0 {
-                return false;
+            if self.children[i].is_none() {
+                self.children[i] = Some(ch);
+                self.keys[key as usize] = i as i8;
+                self.len += 1;
+                return;
             }
         }
-        true 
     }
-    
+
     // Node48
-    fn get_children(&mut self) -> Vec<Option<Rc<RefCell<dyn ArtNode>>>> {
-        let mut v: Vec<Option<Rc<RefCell<dyn ArtNode>>>> = Vec::new();
-        v.clone_from(&self.children);
-        v
+    fn del_child(&mut self, key: u8) -> Option<Node<V>> {
+        let idx = self.keys[key as usize];
+        if idx < 0 {
+            return None;
+        }
+        self.keys[key as usize] = -1;
+        self.len -= 1;
+        self.header.deletes = self.header.deletes.saturating_add(1);
+        self.children[idx as usize].take()
     }
 
     // Node48
-    fn shrink(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut node16 = Node16::new(self.edge);
-        let mut csize = 0usize;
-       
-        // copy values to new node
-        node16.values.clone_from(&mut self.values);
-        
-        // copy children to new node
-        for i in 0..48 {
-            match self.children[i] {
-                Some(ref rc) => {
-                    node16.children[csize] = Some(rc.clone());
-                    node16.keys[csize] = rc.borrow().get_edge();
-                    csize += 1;
-                },
-                None => {},
-            }
-            if csize >= 16 {
-                break;
+    fn children(&self) -> Vec<(u8, &Node<V>)> {
+        let mut v = Vec::with_capacity(self.len as usize);
+        for key in 0..256 {
+            let idx = self.keys[key];
+            if idx >= 0 {
+                v.push((key as u8, self.children[idx as usize].as_ref().unwrap()));
             }
         }
+        v
+    }
 
-        // set parent for new node
-        match self.parent {
-            Some(ref rc) => node16.parent = Some(rc.clone()),
-            None => {
-                println!("Parent node can't be None");
-                return None;
-            }
-        }
-        let rc = Rc::new(RefCell::new(node16));
-        
-        // set children's parent to new node
-        let chs = rc.borrow_mut().get_children();
-        for cn in chs.iter() {
-            match *cn {
-                Some(ref crc) => crc.borrow_mut().set_parent(Some(rc.clone())),
-                None => {},
-            }
-        }
-        Some(rc.clone())
+    // Node48
+    fn child_count(&self) -> usize {
+        self.len as usize
     }
-    
+
     // Node48
-    fn expand(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut node256 = Node256::new(self.edge);
-        
-        // copy values to new node
-        node256.values.clone_from(&mut self.values);
-        
-        // copy children to new node
-        for i in 0..48 {
-            match self.children[i] {
-                Some(ref rc) => {
-                    let idx = rc.borrow().get_edge();
-                    node256.children[idx as usize] = Some(rc.clone());
-                },
-                None => {},
-            }
-        }
-        
-        // set parent for new node
-        match self.parent {
-            Some(ref rc) => node256.parent = Some(rc.clone()),
-            None => {
-                println!("Parent node can't be None");
-                return None;
-            }
-        }
-        let rc = Rc::new(RefCell::new(node256));
-        
-        // set children's parent to new node
-        let chs = rc.borrow_mut().get_children();
-        for cn in chs.iter() {
-            match *cn {
-                Some(ref crc) => crc.borrow_mut().set_parent(Some(rc.clone())),
-                None => {},
+    fn child_at_or_after(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for k in key as usize..256 {
+            let idx = self.keys[k];
+            if idx >= 0 {
+                return Some((k as u8, self.children[idx as usize].as_ref().unwrap()));
             }
         }
-        Some(rc.clone())
+        None
     }
 
     // Node48
-    fn get_edge(&self) -> u8 {
-        self.edge
+    fn child_at_or_before(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for k in (0..key as usize + 1).rev() {
+            let idx = self.keys[k];
+            if idx >= 0 {
+                return Some((k as u8, self.children[idx as usize].as_ref().unwrap()));
+            }
+        }
+        None
     }
-    
+
     // Node48
-    fn need_expand(&self) -> bool {
-        let mut full = true; 
-        for i in 0..48 {
-            if self.keys[i] < 0 {
-                full = false;
-                break;
+    fn keys_before(&self, key: u8) -> usize {
+        let mut n = 0usize;
+        for k in 0..key as usize {
+            let idx = self.keys[k];
+            if idx >= 0 {
+                n += self.children[idx as usize].as_ref().unwrap().key_count();
             }
         }
-        full
+        n
     }
-    
+
     // Node48
-    fn need_shrink(&self) -> bool {
-        let mut count = 0i32;
-        for i in 0..16 {
-            if self.keys[i] >= 0 {
-                count += 1;
+    fn check(&self) -> Result<(), &'static str> {
+        let mut used = [false; 48];
+        for &idx in self.keys.iter() {
+            if idx == -1 {
+                continue;
+            }
+            if idx < 0 || idx as usize >= 48 {
+                return Err("key points outside the slots");
             }
+            if used[idx as usize] || self.children[idx as usize].is_none() {
+                return Err("key points to a taken or empty slot");
+            }
+            used[idx as usize] = true;
         }
-        if count < 15 {
-            return true;
+        if used.iter().filter(|&&u| u).count() != self.len as usize {
+            return Err("keys do not match len");
         }
-        false
-    }
-    
-    // Node48
-    fn set_value(&mut self, val: String) {
-        self.values.push(val);
+        if self.children.iter().zip(used.iter()).any(|(ch, &u)| ch.is_some() && !u) {
+            return Err("child without key");
+        }
+        Ok(())
     }
-    
+
     // Node48
-    fn get_values(&self) -> Option<Vec<String>> {
-        let mut r: Vec<String> = Vec::new();
-        r.clone_from(&self.values);
-        Some(r)
+    fn need_expand(&self) -> bool {
+        self.len == 48
     }
-    
+
     // Node48
-    fn del_values(&mut self) {
-        self.values.clear();
+    fn need_shrink(&self, policy: &dyn ResizePolicy) -> bool {
+        policy.shrink(NodeKind::N48, self.len as usize, self.header.deletes as usize)
     }
-    
+
     // Node48
-    fn set_parent(&mut self, pare: Option<Rc<RefCell<dyn ArtNode>>>) {
-        self.parent = pare;
+    fn expand(&mut self) -> Node<V> {
+        let mut node256 = Node256::new(&[]);
+        node256.header = self.header.take();
+
+        // move children to new node
+        for key in 0..256 {
+            let idx = self.keys[key];
+            if idx >= 0 {
+                node256.children[key] = self.children[idx as usize].take();
+                self.keys[key] = -1;
+            }
+        }
+        node256.len = self.len as u16;
+        self.len = 0;
+        Node::N256(Arc::new(node256))
     }
-    
+
     // Node48
-    fn get_parent(&self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        match self.parent {
-            Some(ref rc) => return Some(rc.clone()),
-            None => return None,
+    fn shrink(&mut self) -> Node<V> {
+        let mut node16 = Node16::new(&[]);
+        node16.header = self.header.take();
+        let mut csize = 0usize;
+
+        // move children to new node
+        for key in 0..256 {
+            let idx = self.keys[key];
+            if idx >= 0 {
+                node16.keys[csize] = key as u8;
+                node16.children[csize] = self.children[idx as usize].take();
+                self.keys[key] = -1;
+                csize += 1;
+            }
         }
+        node16.len = csize as u8;
+        self.len = 0;
+        Node::N16(Arc::new(node16))
     }
 }
 
-impl ArtNode for Node256 {
-    
-    // Node256
-    fn set_child(&mut self, key: u8, ch: Option<Rc<RefCell<dyn ArtNode>>>) -> bool {
-        match self.children[key as usize] {
-            Some(ref rc) => rc.borrow_mut().set_parent(None),
-            None => {},
-        }
-        self.children[key as usize] = ch;
-        true
-    }
+impl<V> ArtNode<V> for Node256<V> {
 
     // Node256
-    fn get_child(&mut self, key: u8) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        match self.children[key as usize] {
-            Some(ref rc) => return Some(rc.clone()),
-            None => return None,
-        }
+    fn header(&self) -> &Header<V> {
+        &self.header
     }
 
     // Node256
-    fn del_child(&mut self, key: u8) {
-        match self.children[key as usize] {
-            Some(ref rc) => rc.borrow_mut().set_parent(None),
-            None => {},
-        }
-        self.children[key as usize] = None;
+    fn header_mut(&mut self) -> &mut Header<V> {
+        &mut self.header
     }
-    
+
     // Node256
-    fn empty(&self) -> bool {
-        for i in 0..256 {
-            match self.children[i] {
-                Some(ref _rc) => return false,
-                None => {},
-            }
-        }
-        true 
+    fn find_child(&self, key: u8) -> Option<&Node<V>> {
+        self.children[key as usize].as_ref()
     }
-    
+
     // Node256
-    fn get_children(&mut self) -> Vec<Option<Rc<RefCell<dyn ArtNode>>>> {
-        let mut v: Vec<Option<Rc<RefCell<dyn ArtNode>>>> = Vec::new();
-        v.clone_from(&self.children);
-        v
+    fn find_child_mut(&mut self, key: u8) -> Option<&mut Node<V>> {
+        self.children[key as usize].as_mut()
     }
 
     // Node256
-    fn shrink(&mut self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        let mut node48 = Node48::new(self.edge);
-        
-        // copy values to new node
-        node48.values.clone_from(&mut self.values);
-        
-        // copy children to new node
-        for i in 0..256 {
-            match self.children[i] {
-                Some(ref rc) => {
-                    let key = rc.borrow().get_edge();
-                    node48.children.push(Some(rc.clone()));
-                    node48.keys[key as usize] = (node48.children.len()-1) as i8;
-                },
-                None => {},
-            }
-        }
-        
-        // set parent for new node
-        match self.parent {
-            Some(ref rc) => node48.parent = Some(rc.clone()),
-            None => {
-                println!("Parent node can't be None");
-                return None;
-            }
-        }
-        let rc = Rc::new(RefCell::new(node48));
-        
-        // set children's parent to new node
-        let chs = rc.borrow_mut().get_children();
-        for cn in chs.iter() {
-            match *cn {
-                Some(ref crc) => crc.borrow_mut().set_parent(Some(rc.clone())),
-                None => {},
-            }
+    fn add_child(&mut self, key: u8, ch: Node<V>) {
+        if self.children[key as usize].is_none() {
+            self.len += 1;
         }
-        Some(rc.clone())
+        self.children[key as usize] = Some(ch);
     }
 
     // Node256
-    fn get_edge(&self) -> u8 {
-        self.edge
-    }
-    
-    // Node256
-    fn need_expand(&self) -> bool {
-        false
+    fn del_child(&mut self, key: u8) -> Option<Node<V>> {
+        let ch = self.children[key as usize].take();
+        if ch.is_some() {
+            self.len -= 1;
+            self.header.deletes = self.header.deletes.saturating_add(1);
+        }
+        ch
     }
-    
+
     // Node256
-    fn need_shrink(&self) -> bool {
-        let mut count = 0i32;
-        for i in 0..256 {
-            if self.children[i].is_some() {
-                count += 1;
+    fn children(&self) -> Vec<(u8, &Node<V>)> {
+        let mut v = Vec::with_capacity(self.len as usize);
+        for key in 0..256 {
+            if let Some(ref ch) = self.children[key] {
+                v.push((key as u8, ch));
             }
         }
-        if count < 46 {
-            return true;
-        }
-        false
-    }
-    
-    // Node256
-    fn set_value(&mut self, val: String) {
-        self.values.push(val);
+        v
     }
-    
+
     // Node256
-    fn get_values(&self) -> Option<Vec<String>> {
-        let mut r: Vec<String> = Vec::new();
-        r.clone_from(&self.values);
-        Some(r)
+    fn child_count(&self) -> usize {
+        self.len as usize
     }
-    
+
     // Node256
-    fn del_values(&mut self) {
-        self.values.clear();
+    fn child_at_or_after(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for k in key as usize..256 {
+            if let Some(ref ch) = self.children[k] {
+                return Some((k as u8, ch));
+            }
+        }
+        None
     }
-    
+
     // Node256
-    fn set_parent(&mut self, pare: Option<Rc<RefCell<dyn ArtNode>>>) {
-        self.parent = pare;
+    fn child_at_or_before(&self, key: u8) -> Option<(u8, &Node<V>)> {
+        for k in (0..key as usize + 1).rev() {
+            if let Some(ref ch) = self.children[k] {
+                return Some((k as u8, ch));
+            }
+        }
+        None
     }
+
     // Node256
-    fn get_parent(&self) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        match self.parent {
-            Some(ref rc) => return Some(rc.clone()),
-            None => return None,
+    fn keys_before(&self, key: u8) -> usize {
+        let mut n = 0usize;
+        for ch in self.children[..key as usize].iter() {
+            if let Some(ref ch) = *ch {
+                n += ch.key_count();
+            }
         }
+        n
     }
-}
-
-pub struct Sentinel {
-    child: Option<Rc<RefCell<dyn ArtNode>>>,
-}
 
-impl Sentinel {
-    pub fn new() -> Sentinel {
-        Sentinel {
-            child: None,
+    // Node256
+    fn check(&self) -> Result<(), &'static str> {
+        if self.children.iter().filter(|ch| ch.is_some()).count() != self.len as usize {
+            return Err("children do not match len");
         }
+        Ok(())
     }
 
-}
+    // Node256
+    fn need_expand(&self) -> bool {
+        false
+    }
 
-impl ArtNode for Sentinel {
-    fn set_child(&mut self, _key: u8, ch: Option<Rc<RefCell<dyn ArtNode>>>) -> bool {
-        match self.child {
-            Some(ref rc) => rc.borrow_mut().set_parent(None),
-            None => {},
-        }
-        self.child = ch;
-        true
+    // Node256
+    fn need_shrink(&self, policy: &dyn ResizePolicy) -> bool {
+        policy.shrink(NodeKind::N256, self.len as usize, self.header.deletes as usize)
     }
 
-    fn get_child(&mut self, _key: u8) -> Option<Rc<RefCell<dyn ArtNode>>> {
-        match self.child {
-            Some(ref rc) => return Some(rc.clone()),
-            None => return None,
-        }
+    // Node256
+    fn expand(&mut self) -> Node<V> {
+        unreachable!("Node256 can't expand")
     }
 
-    fn del_child(&mut self, _key: u8) {
-        match self.child {
-            Some(ref rc) => rc.borrow_mut().set_parent(None),
-            None => {},
+    // Node256
+    fn shrink(&mut self) -> Node<V> {
+        let mut node48 = Node48::new(&[]);
+        node48.header = self.header.take();
+        let mut csize = 0usize;
+
+        // move children to new node
+        for key in 0..256 {
+            if self.children[key].is_some() {
+                node48.children[csize] = self.children[key].take();
+                node48.keys[key] = csize as i8;
+                csize += 1;
+            }
         }
-        self.child = None;
+        node48.len = csize as u8;
+        self.len = 0;
+        Node::N48(Arc::new(node48))
     }
 }
-
-
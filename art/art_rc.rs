@@ -0,0 +1,341 @@
+// The Art as it was before it was rebuilt around an owned enum node, kept
+// for bench.rs to measure lookups against.
+//
+// Every node is an Rc<RefCell<dyn ArtNode>>, one per key byte, without path
+// compression, and a lookup borrows each node and clones the Rc of the next.
+// Only insert and search are left. Two changes from the old code: a node no
+// longer points to its parent, insert remembers the parent on the way down,
+// and Node48 takes its 17th child, the old one indexed past its children.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Child = Option<Rc<RefCell<dyn ArtNode>>>;
+
+pub trait ArtNode {
+    fn get_edge(&self) -> u8;
+    fn get_child(&mut self, key: u8) -> Child;
+    // false when the node is full
+    fn set_child(&mut self, key: u8, ch: Child) -> bool;
+    fn expand(&mut self) -> Child {
+        None
+    }
+    fn set_value(&mut self, val: String);
+    fn get_values(&self) -> Option<Vec<String>>;
+}
+
+pub struct Art {
+    root: Rc<RefCell<dyn ArtNode>>,
+    size: usize,
+}
+
+impl Art {
+    pub fn new() -> Art {
+        Art {
+            root: Rc::new(RefCell::new(Node4::new(0))),
+            size: 1,
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn insert(&mut self, key: String, val: String) {
+        let mut parent: Child = None;
+        let mut curr_node = self.root.clone();
+        for &c in key.as_bytes() {
+            let cn = curr_node.borrow_mut().get_child(c);
+            if let Some(cn) = cn {
+                parent = Some(curr_node);
+                curr_node = cn;
+                continue;
+            }
+            let rc: Rc<RefCell<dyn ArtNode>> = Rc::new(RefCell::new(Node4::new(c)));
+            if !curr_node.borrow_mut().set_child(c, Some(rc.clone())) {
+                // full, put a bigger node in the place of this one
+                let bigger = curr_node.borrow_mut().expand().unwrap();
+                bigger.borrow_mut().set_child(c, Some(rc.clone()));
+                let edge = curr_node.borrow().get_edge();
+                match parent {
+                    Some(ref p) => p.borrow_mut().set_child(edge, Some(bigger.clone())),
+                    None => {
+                        self.root = bigger.clone();
+                        true
+                    },
+                };
+                curr_node = bigger;
+            }
+            self.size += 1;
+            parent = Some(curr_node);
+            curr_node = rc;
+        }
+        curr_node.borrow_mut().set_value(val);
+    }
+
+    pub fn search(&self, key: &str) -> Option<Vec<String>> {
+        let mut curr_node = self.root.clone();
+        for &c in key.as_bytes() {
+            let cn = curr_node.borrow_mut().get_child(c)?;
+            curr_node = cn;
+        }
+        let r = curr_node.borrow().get_values();
+        r
+    }
+}
+
+pub struct Node4 {
+    edge: u8,
+    keys: [u8; 4],
+    children: Vec<Child>,
+    values: Vec<String>,
+}
+
+pub struct Node16 {
+    edge: u8,
+    keys: [u8; 16],
+    children: Vec<Child>,
+    values: Vec<String>,
+}
+
+pub struct Node48 {
+    edge: u8,
+    keys: [i8; 256],
+    children: Vec<Child>,
+    values: Vec<String>,
+}
+
+pub struct Node256 {
+    edge: u8,
+    children: Vec<Child>,
+    values: Vec<String>,
+}
+
+impl Node4 {
+    pub fn new(edge: u8) -> Node4 {
+        let mut node = Node4 {
+            edge,
+            keys: [0; 4],
+            children: Vec::new(),
+            values: Vec::new(),
+        };
+        node.children.resize_with(4, || None);
+        node
+    }
+}
+
+impl Node16 {
+    pub fn new(edge: u8) -> Node16 {
+        let mut node = Node16 {
+            edge,
+            keys: [0; 16],
+            children: Vec::new(),
+            values: Vec::new(),
+        };
+        node.children.resize_with(16, || None);
+        node
+    }
+}
+
+impl Node48 {
+    pub fn new(edge: u8) -> Node48 {
+        Node48 {
+            edge,
+            keys: [-1; 256],
+            children: Vec::with_capacity(48),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl Node256 {
+    pub fn new(edge: u8) -> Node256 {
+        let mut node = Node256 {
+            edge,
+            children: Vec::new(),
+            values: Vec::new(),
+        };
+        node.children.resize_with(256, || None);
+        node
+    }
+}
+
+// Node4 and Node16: the keys are looked at in order, an empty slot has no child
+fn sorted_get(keys: &[u8], children: &[Child], key: u8) -> Child {
+    let idx = (0..keys.len()).find(|&i| keys[i] == key && children[i].is_some())?;
+    children[idx].clone()
+}
+
+fn sorted_set(keys: &mut [u8], children: &mut [Child], key: u8, ch: Child) -> bool {
+    for i in 0..keys.len() {
+        if keys[i] == key && children[i].is_some() {
+            children[i] = ch;
+            return true;
+        }
+    }
+    match children.iter().position(|c| c.is_none()) {
+        Some(i) => {
+            children[i] = ch;
+            keys[i] = key;
+            true
+        },
+        None => false,
+    }
+}
+
+impl ArtNode for Node4 {
+    // Node4
+    fn get_edge(&self) -> u8 {
+        self.edge
+    }
+
+    // Node4
+    fn get_child(&mut self, key: u8) -> Child {
+        sorted_get(&self.keys, &self.children, key)
+    }
+
+    // Node4
+    fn set_child(&mut self, key: u8, ch: Child) -> bool {
+        sorted_set(&mut self.keys, &mut self.children, key, ch)
+    }
+
+    // Node4
+    fn expand(&mut self) -> Child {
+        let mut node16 = Node16::new(self.edge);
+        node16.values.clone_from(&self.values);
+        for i in 0..4 {
+            node16.children[i] = self.children[i].clone();
+            node16.keys[i] = self.keys[i];
+        }
+        Some(Rc::new(RefCell::new(node16)))
+    }
+
+    // Node4
+    fn set_value(&mut self, val: String) {
+        self.values.push(val);
+    }
+
+    // Node4
+    fn get_values(&self) -> Option<Vec<String>> {
+        Some(self.values.clone())
+    }
+}
+
+impl ArtNode for Node16 {
+    // Node16
+    fn get_edge(&self) -> u8 {
+        self.edge
+    }
+
+    // Node16
+    fn get_child(&mut self, key: u8) -> Child {
+        sorted_get(&self.keys, &self.children, key)
+    }
+
+    // Node16
+    fn set_child(&mut self, key: u8, ch: Child) -> bool {
+        sorted_set(&mut self.keys, &mut self.children, key, ch)
+    }
+
+    // Node16
+    fn expand(&mut self) -> Child {
+        let mut node48 = Node48::new(self.edge);
+        node48.values.clone_from(&self.values);
+        for rc in self.children.iter().flatten() {
+            let key = rc.borrow().get_edge();
+            node48.children.push(Some(rc.clone()));
+            node48.keys[key as usize] = (node48.children.len() - 1) as i8;
+        }
+        Some(Rc::new(RefCell::new(node48)))
+    }
+
+    // Node16
+    fn set_value(&mut self, val: String) {
+        self.values.push(val);
+    }
+
+    // Node16
+    fn get_values(&self) -> Option<Vec<String>> {
+        Some(self.values.clone())
+    }
+}
+
+impl ArtNode for Node48 {
+    // Node48
+    fn get_edge(&self) -> u8 {
+        self.edge
+    }
+
+    // Node48
+    fn get_child(&mut self, key: u8) -> Child {
+        let idx = self.keys[key as usize];
+        if idx < 0 {
+            return None;
+        }
+        self.children[idx as usize].clone()
+    }
+
+    // Node48
+    fn set_child(&mut self, key: u8, ch: Child) -> bool {
+        let idx = self.keys[key as usize];
+        if idx >= 0 {
+            self.children[idx as usize] = ch;
+            return true;
+        }
+        if self.children.len() == 48 {
+            return false;
+        }
+        self.children.push(ch);
+        self.keys[key as usize] = (self.children.len() - 1) as i8;
+        true
+    }
+
+    // Node48
+    fn expand(&mut self) -> Child {
+        let mut node256 = Node256::new(self.edge);
+        node256.values.clone_from(&self.values);
+        for rc in self.children.iter().flatten() {
+            let key = rc.borrow().get_edge();
+            node256.children[key as usize] = Some(rc.clone());
+        }
+        Some(Rc::new(RefCell::new(node256)))
+    }
+
+    // Node48
+    fn set_value(&mut self, val: String) {
+        self.values.push(val);
+    }
+
+    // Node48
+    fn get_values(&self) -> Option<Vec<String>> {
+        Some(self.values.clone())
+    }
+}
+
+impl ArtNode for Node256 {
+    // Node256
+    fn get_edge(&self) -> u8 {
+        self.edge
+    }
+
+    // Node256
+    fn get_child(&mut self, key: u8) -> Child {
+        self.children[key as usize].clone()
+    }
+
+    // Node256
+    fn set_child(&mut self, key: u8, ch: Child) -> bool {
+        self.children[key as usize] = ch;
+        true
+    }
+
+    // Node256
+    fn set_value(&mut self, val: String) {
+        self.values.push(val);
+    }
+
+    // Node256
+    fn get_values(&self) -> Option<Vec<String>> {
+        Some(self.values.clone())
+    }
+}
@@ -0,0 +1,578 @@
+// ART for u64 keys, taken as 8 big-endian bytes so the tree is in key order.
+//
+// Every key has the same length, so no key ends inside the tree: nodes have
+// no own leaf and no terminator byte is needed. A node does not store its
+// compressed path either. It keeps one key from below it and the byte it
+// branches on, the bytes above that are the path, so splitting a path or
+// pulling a child up never rewrites anything. Leaves are the key and value
+// stored inline in the slot of their parent, not separate allocations.
+//
+// The node types are not the ones of Art: a child slot holds an inner node
+// or an inline leaf, and nodes keep a head instead of a prefix. Node16 finds
+// its keys with find_key16 of Art. Nodes grow and shrink at the thresholds
+// of Thresholds::default(), ArtConfig and its resize policies are not
+// supported.
+
+use std::convert::TryInto;
+use std::mem;
+
+use crate::art::find_key16;
+
+enum Child<V> {
+    Leaf(u64, V),
+    N4(Box<Sorted<V, 4>>),
+    N16(Box<Sorted<V, 16>>),
+    N48(Box<Node48<V>>),
+    N256(Box<Node256<V>>),
+}
+
+// a key below the node and the byte of it the node branches on
+#[derive(Clone, Copy)]
+struct Head {
+    key: u64,
+    depth: u8,
+}
+
+// Node4 and Node16: keys sorted in the first len slots
+struct Sorted<V, const N: usize> {
+    head: Head,
+    len: u8,
+    keys: [u8; N],
+    children: [Option<Child<V>>; N],
+}
+
+struct Node48<V> {
+    head: Head,
+    len: u8,
+    // slot of the child in children plus one, 0 if none
+    keys: [u8; 256],
+    children: [Option<Child<V>>; 48],
+}
+
+struct Node256<V> {
+    head: Head,
+    len: u16,
+    children: [Option<Child<V>>; 256],
+}
+
+fn byte(key: u64, depth: usize) -> u8 {
+    (key >> (56 - 8 * depth)) as u8
+}
+
+// a and b have the same first depth bytes
+fn same_path(a: u64, b: u64, depth: usize) -> bool {
+    depth == 0 || (a ^ b) >> (64 - 8 * depth) == 0
+}
+
+// first byte where a and b differ, they must differ
+fn first_diff(a: u64, b: u64) -> usize {
+    ((a ^ b).leading_zeros() / 8) as usize
+}
+
+trait Inner<V> {
+    fn head(&self) -> Head;
+    fn len(&self) -> usize;
+    fn find_child_mut(&mut self, c: u8) -> Option<&mut Child<V>>;
+    // caller makes sure the node is not full
+    fn add_child(&mut self, c: u8, ch: Child<V>);
+    fn del_child(&mut self, c: u8) -> Option<Child<V>>;
+    // edges and children in key order
+    fn children(&self) -> Vec<(u8, &Child<V>)>;
+    // move the children out, in key order
+    fn take_children(&mut self) -> Vec<(u8, Child<V>)>;
+    fn need_expand(&self) -> bool;
+    fn need_shrink(&self) -> bool;
+}
+
+impl<V, const N: usize> Sorted<V, N> {
+    fn new(head: Head) -> Sorted<V, N> {
+        Sorted {
+            head,
+            len: 0,
+            keys: [0; N],
+            children: std::array::from_fn(|_| None),
+        }
+    }
+
+    fn find_index(&self, c: u8) -> Option<usize> {
+        if N == 4 {
+            return self.keys[..self.len as usize].iter().position(|&k| k == c);
+        }
+        find_key16(self.keys[..].try_into().unwrap(), self.len as usize, c)
+    }
+}
+
+impl<V, const N: usize> Inner<V> for Sorted<V, N> {
+    fn head(&self) -> Head {
+        self.head
+    }
+
+    fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    fn find_child_mut(&mut self, c: u8) -> Option<&mut Child<V>> {
+        let i = self.find_index(c)?;
+        self.children[i].as_mut()
+    }
+
+    fn add_child(&mut self, c: u8, ch: Child<V>) {
+        let mut i = self.len as usize;
+        while i > 0 && self.keys[i - 1] > c {
+            self.keys[i] = self.keys[i - 1];
+            self.children[i] = self.children[i - 1].take();
+            i -= 1;
+        }
+        self.keys[i] = c;
+        self.children[i] = Some(ch);
+        self.len += 1;
+    }
+
+    fn del_child(&mut self, c: u8) -> Option<Child<V>> {
+        let i = self.find_index(c)?;
+        let ch = self.children[i].take();
+        for j in i + 1..self.len as usize {
+            self.keys[j - 1] = self.keys[j];
+            self.children[j - 1] = self.children[j].take();
+        }
+        self.len -= 1;
+        ch
+    }
+
+    fn children(&self) -> Vec<(u8, &Child<V>)> {
+        (0..self.len as usize).map(|i| (self.keys[i], self.children[i].as_ref().unwrap())).collect()
+    }
+
+    fn take_children(&mut self) -> Vec<(u8, Child<V>)> {
+        let len = mem::replace(&mut self.len, 0) as usize;
+        (0..len).map(|i| (self.keys[i], self.children[i].take().unwrap())).collect()
+    }
+
+    fn need_expand(&self) -> bool {
+        self.len as usize == N
+    }
+
+    fn need_shrink(&self) -> bool {
+        N == 16 && self.len < 3
+    }
+}
+
+impl<V> Inner<V> for Node48<V> {
+    fn head(&self) -> Head {
+        self.head
+    }
+
+    fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    fn find_child_mut(&mut self, c: u8) -> Option<&mut Child<V>> {
+        match self.keys[c as usize] {
+            0 => None,
+            i => self.children[i as usize - 1].as_mut(),
+        }
+    }
+
+    fn add_child(&mut self, c: u8, ch: Child<V>) {
+        let i = self.children.iter().position(|ch| ch.is_none()).unwrap();
+        self.children[i] = Some(ch);
+        self.keys[c as usize] = i as u8 + 1;
+        self.len += 1;
+    }
+
+    fn del_child(&mut self, c: u8) -> Option<Child<V>> {
+        match mem::replace(&mut self.keys[c as usize], 0) {
+            0 => None,
+            i => {
+                self.len -= 1;
+                self.children[i as usize - 1].take()
+            },
+        }
+    }
+
+    fn children(&self) -> Vec<(u8, &Child<V>)> {
+        (0..256).filter(|&c| self.keys[c] != 0)
+                .map(|c| (c as u8, self.children[self.keys[c] as usize - 1].as_ref().unwrap()))
+                .collect()
+    }
+
+    fn take_children(&mut self) -> Vec<(u8, Child<V>)> {
+        let mut v = Vec::with_capacity(self.len as usize);
+        for c in 0..256 {
+            if let Some(ch) = self.del_child(c as u8) {
+                v.push((c as u8, ch));
+            }
+        }
+        v
+    }
+
+    fn need_expand(&self) -> bool {
+        self.len == 48
+    }
+
+    fn need_shrink(&self) -> bool {
+        self.len < 15
+    }
+}
+
+impl<V> Inner<V> for Node256<V> {
+    fn head(&self) -> Head {
+        self.head
+    }
+
+    fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    fn find_child_mut(&mut self, c: u8) -> Option<&mut Child<V>> {
+        self.children[c as usize].as_mut()
+    }
+
+    fn add_child(&mut self, c: u8, ch: Child<V>) {
+        self.children[c as usize] = Some(ch);
+        self.len += 1;
+    }
+
+    fn del_child(&mut self, c: u8) -> Option<Child<V>> {
+        let ch = self.children[c as usize].take();
+        if ch.is_some() {
+            self.len -= 1;
+        }
+        ch
+    }
+
+    fn children(&self) -> Vec<(u8, &Child<V>)> {
+        (0..256).filter_map(|c| self.children[c].as_ref().map(|ch| (c as u8, ch))).collect()
+    }
+
+    fn take_children(&mut self) -> Vec<(u8, Child<V>)> {
+        self.len = 0;
+        (0..256).filter_map(|c| self.children[c].take().map(|ch| (c as u8, ch))).collect()
+    }
+
+    fn need_expand(&self) -> bool {
+        false
+    }
+
+    fn need_shrink(&self) -> bool {
+        self.len < 46
+    }
+}
+
+// a node of the smallest type with room for room children, holding children
+fn make_node<V>(head: Head, room: usize, children: Vec<(u8, Child<V>)>) -> Child<V> {
+    let mut node = match room.max(children.len()) {
+        0..=4 => Child::N4(Box::new(Sorted::new(head))),
+        5..=16 => Child::N16(Box::new(Sorted::new(head))),
+        17..=48 => Child::N48(Box::new(Node48 {
+            head,
+            len: 0,
+            keys: [0; 256],
+            children: std::array::from_fn(|_| None),
+        })),
+        _ => Child::N256(Box::new(Node256 {
+            head,
+            len: 0,
+            children: std::array::from_fn(|_| None),
+        })),
+    };
+    {
+        let n = node.inner_mut().unwrap();
+        for (c, ch) in children {
+            n.add_child(c, ch);
+        }
+    }
+    node
+}
+
+impl<V> Child<V> {
+    fn inner(&self) -> Option<&dyn Inner<V>> {
+        match *self {
+            Child::N4(ref n) => Some(&**n),
+            Child::N16(ref n) => Some(&**n),
+            Child::N48(ref n) => Some(&**n),
+            Child::N256(ref n) => Some(&**n),
+            Child::Leaf(..) => None,
+        }
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn Inner<V>> {
+        match *self {
+            Child::N4(ref mut n) => Some(&mut **n),
+            Child::N16(ref mut n) => Some(&mut **n),
+            Child::N48(ref mut n) => Some(&mut **n),
+            Child::N256(ref mut n) => Some(&mut **n),
+            Child::Leaf(..) => None,
+        }
+    }
+
+    // static dispatch for the lookup path
+    fn find_child(&self, c: u8) -> Option<&Child<V>> {
+        match *self {
+            Child::N4(ref n) => n.children[n.find_index(c)?].as_ref(),
+            Child::N16(ref n) => n.children[n.find_index(c)?].as_ref(),
+            Child::N48(ref n) => match n.keys[c as usize] {
+                0 => None,
+                i => n.children[i as usize - 1].as_ref(),
+            },
+            Child::N256(ref n) => n.children[c as usize].as_ref(),
+            Child::Leaf(..) => None,
+        }
+    }
+}
+
+pub struct ArtU64<V> {
+    root: Option<Child<V>>,
+    len: usize,
+}
+
+impl<V> ArtU64<V> {
+    pub fn new() -> ArtU64<V> {
+        ArtU64 {
+            root: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // set the value of key, returns the value it replaces
+    pub fn insert(&mut self, key: u64, val: V) -> Option<V> {
+        let old = match self.root {
+            Some(ref mut node) => insert_rec(node, key, val),
+            None => {
+                self.root = Some(Child::Leaf(key, val));
+                None
+            },
+        };
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn search(&self, key: u64) -> Option<&V> {
+        let mut node = self.root.as_ref()?;
+        loop {
+            let head = match *node {
+                Child::Leaf(k, ref v) => return if k == key { Some(v) } else { None },
+                Child::N4(ref n) => n.head,
+                Child::N16(ref n) => n.head,
+                Child::N48(ref n) => n.head,
+                Child::N256(ref n) => n.head,
+            };
+            let depth = head.depth as usize;
+            if !same_path(head.key, key, depth) {
+                return None;
+            }
+            node = node.find_child(byte(key, depth))?;
+        }
+    }
+
+    pub fn search_mut(&mut self, key: u64) -> Option<&mut V> {
+        let mut node = self.root.as_mut()?;
+        loop {
+            if let Child::Leaf(k, ref mut v) = *node {
+                return if k == key { Some(v) } else { None };
+            }
+            let n = node.inner_mut().unwrap();
+            let depth = n.head().depth as usize;
+            if !same_path(n.head().key, key, depth) {
+                return None;
+            }
+            node = n.find_child_mut(byte(key, depth))?;
+        }
+    }
+
+    // remove key, returns its value
+    pub fn delete(&mut self, key: u64) -> Option<V> {
+        let val = match self.root {
+            Some(Child::Leaf(k, _)) => {
+                if k != key {
+                    return None;
+                }
+                match self.root.take() {
+                    Some(Child::Leaf(_, v)) => v,
+                    _ => unreachable!(),
+                }
+            },
+            Some(ref mut node) => delete_rec(node, key)?,
+            None => return None,
+        };
+        self.len -= 1;
+        Some(val)
+    }
+
+    // Values of many keys, in the order of keys. The keys are looked up in
+    // sorted order, so keys which share a path walk it once.
+    pub fn get_many(&self, keys: &[u64]) -> Vec<Option<&V>> {
+        let mut out = vec![None; keys.len()];
+        let mut sorted: Vec<(u64, usize)> = keys.iter().enumerate().map(|(i, &k)| (k, i)).collect();
+        sorted.sort_unstable();
+        if let Some(ref root) = self.root {
+            get_many_rec(root, &sorted, &mut out);
+        }
+        out
+    }
+
+    // keys and values in key order
+    pub fn iter<'a>(&'a self) -> Iter<'a, V> {
+        Iter {
+            stack: self.root.iter().collect(),
+        }
+    }
+}
+
+fn insert_rec<V>(node: &mut Child<V>, key: u64, val: V) -> Option<V> {
+    let split = match *node {
+        Child::Leaf(k, ref mut v) => {
+            if k == key {
+                return Some(mem::replace(v, val));
+            }
+            first_diff(k, key)
+        },
+        _ => {
+            let head = node.inner().unwrap().head();
+            let depth = head.depth as usize;
+            if same_path(head.key, key, depth) {
+                let c = byte(key, depth);
+                if node.find_child(c).is_some() {
+                    return insert_rec(node.inner_mut().unwrap().find_child_mut(c).unwrap(), key, val);
+                }
+                let n = node.inner_mut().unwrap();
+                if n.need_expand() {
+                    let children = n.take_children();
+                    *node = make_node(head, children.len() + 1, children);
+                }
+                node.inner_mut().unwrap().add_child(c, Child::Leaf(key, val));
+                return None;
+            }
+            first_diff(head.key, key)
+        },
+    };
+
+    // key leaves the path above node: a node4 at the first differing byte
+    // takes node and the new leaf
+    let head = Head {
+        key,
+        depth: split as u8,
+    };
+    let old = mem::replace(node, make_node(head, 4, Vec::new()));
+    let old_key = match old {
+        Child::Leaf(k, _) => k,
+        ref n => n.inner().unwrap().head().key,
+    };
+    let n = node.inner_mut().unwrap();
+    n.add_child(byte(old_key, split), old);
+    n.add_child(byte(key, split), Child::Leaf(key, val));
+    None
+}
+
+// remove key below inner node, a node left with one child is replaced by it
+fn delete_rec<V>(node: &mut Child<V>, key: u64) -> Option<V> {
+    let val = {
+        let n = node.inner_mut().unwrap();
+        let depth = n.head().depth as usize;
+        if !same_path(n.head().key, key, depth) {
+            return None;
+        }
+        let c = byte(key, depth);
+        let is_leaf = match n.find_child_mut(c) {
+            Some(&mut Child::Leaf(k, _)) => {
+                if k != key {
+                    return None;
+                }
+                true
+            },
+            Some(_) => false,
+            None => return None,
+        };
+        if is_leaf {
+            match n.del_child(c) {
+                Some(Child::Leaf(_, v)) => v,
+                _ => unreachable!(),
+            }
+        } else {
+            delete_rec(n.find_child_mut(c).unwrap(), key)?
+        }
+    };
+
+    let n = node.inner_mut().unwrap();
+    if n.len() == 1 {
+        let (_, only) = n.take_children().pop().unwrap();
+        *node = only;
+    } else if n.need_shrink() {
+        let head = n.head();
+        let children = n.take_children();
+        *node = make_node(head, 0, children);
+    }
+    Some(val)
+}
+
+// keys are sorted and all below the edge to node
+fn get_many_rec<'a, V>(node: &'a Child<V>, keys: &[(u64, usize)], out: &mut Vec<Option<&'a V>>) {
+    let head = match *node {
+        Child::Leaf(k, ref v) => {
+            for &(key, i) in keys {
+                if key == k {
+                    out[i] = Some(v);
+                }
+            }
+            return;
+        },
+        _ => node.inner().unwrap().head(),
+    };
+    let depth = head.depth as usize;
+    // sorted keys on the path of the node are next to each other
+    let mut start = 0usize;
+    while start < keys.len() && !same_path(head.key, keys[start].0, depth) {
+        start += 1;
+    }
+    let mut end = start;
+    while end < keys.len() && same_path(head.key, keys[end].0, depth) {
+        end += 1;
+    }
+    let keys = &keys[start..end];
+
+    // one walk down per child for the run of keys below it
+    let mut start = 0usize;
+    while start < keys.len() {
+        let c = byte(keys[start].0, depth);
+        let mut end = start + 1;
+        while end < keys.len() && byte(keys[end].0, depth) == c {
+            end += 1;
+        }
+        if let Some(ch) = node.find_child(c) {
+            get_many_rec(ch, &keys[start..end], out);
+        }
+        start = end;
+    }
+}
+
+pub struct Iter<'a, V> {
+    stack: Vec<&'a Child<V>>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (u64, &'a V);
+
+    fn next(&mut self) -> Option<(u64, &'a V)> {
+        loop {
+            let node = self.stack.pop()?;
+            match *node {
+                Child::Leaf(k, ref v) => return Some((k, v)),
+                _ => {
+                    for (_, ch) in node.inner().unwrap().children().into_iter().rev() {
+                        self.stack.push(ch);
+                    }
+                },
+            }
+        }
+    }
+}
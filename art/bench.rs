@@ -0,0 +1,307 @@
+#[allow(dead_code)]
+mod arena;
+#[allow(dead_code)]
+mod art;
+#[allow(dead_code)]
+mod art_rc;
+#[allow(dead_code)]
+mod art_u64;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use arena::ArenaArt;
+use art::{Art, ArtNode, Leaf, Node, Node16};
+use art_u64::ArtU64;
+
+// Build with optimizations: rustc -O bench.rs
+
+// counts live heap bytes, so we can see a dropped tree gives everything back
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const N: usize = 200_000;
+
+fn gen_keys(n: usize) -> Vec<String> {
+    let mut seed = 0x2545f4914f6cdd1du64;
+    let mut keys = Vec::with_capacity(n);
+    for i in 0..n {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        // a mix of random and shared-prefix keys
+        if i % 2 == 0 {
+            keys.push(format!("{:x}", seed));
+        } else {
+            keys.push(format!("user:{:08}", seed % 100_000_000));
+        }
+    }
+    keys
+}
+
+fn report(name: &str, start: Instant, n: usize) {
+    let ns = start.elapsed().as_nanos() as f64 / n as f64;
+    println!("{:<24} {:>8.1} ns/op", name, ns);
+}
+
+// lookups in a full Node16, half of the probes miss
+fn bench_node16() {
+    let mut node: Node16<String> = Node16::new(&[]);
+    for i in 0..16u8 {
+        let k = i * 13 + 7;
+        node.add_child(k, Node::Leaf(Leaf::new(&[k], String::new())));
+    }
+    let probes: Vec<u8> = (0..1024u32).map(|i| ((i * 2654435761u32) >> 24) as u8).collect();
+    let rounds = 20_000usize;
+    let n = rounds * probes.len();
+
+    let start = Instant::now();
+    let mut hits = 0usize;
+    for _ in 0..rounds {
+        for k in probes.iter() {
+            hits += black_box(&node).find_index_linear(*k).is_some() as usize;
+        }
+    }
+    report("node16 linear loop", start, n);
+
+    let start = Instant::now();
+    let mut hits2 = 0usize;
+    for _ in 0..rounds {
+        for k in probes.iter() {
+            hits2 += black_box(&node).find_index_binary(*k).is_some() as usize;
+        }
+    }
+    report("node16 binary search", start, n);
+
+    let start = Instant::now();
+    let mut hits3 = 0usize;
+    for _ in 0..rounds {
+        for k in probes.iter() {
+            hits3 += black_box(&node).find_index(*k).is_some() as usize;
+        }
+    }
+    report("node16 find_index", start, n);
+    assert!(hits == hits2 && hits == hits3);
+}
+
+// the same keys in an Art and in an arena tree, the drop of a whole tree timed too
+fn bench_arena(keys: &[String]) {
+    let before = LIVE.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut art = Art::new();
+    for (i, k) in keys.iter().enumerate() {
+        art.insert(k, i);
+    }
+    report("art insert usize", start, keys.len());
+    let art_heap = LIVE.load(Ordering::Relaxed) - before;
+    let start = Instant::now();
+    for k in keys.iter() {
+        assert!(black_box(&art).search(k).is_some());
+    }
+    report("art search usize", start, keys.len());
+    let start = Instant::now();
+    drop(art);
+    report("art drop", start, keys.len());
+
+    let start = Instant::now();
+    let mut arena = ArenaArt::new();
+    for (i, k) in keys.iter().enumerate() {
+        arena.insert(k, i);
+    }
+    report("arena insert usize", start, keys.len());
+    let arena_heap = LIVE.load(Ordering::Relaxed) - before;
+    let start = Instant::now();
+    for k in keys.iter() {
+        assert!(black_box(&arena).search(k).is_some());
+    }
+    report("arena search usize", start, keys.len());
+    let nodes = arena.get_size();
+    let start = Instant::now();
+    drop(arena);
+    report("arena drop", start, keys.len());
+    println!("heap: art {} bytes, arena {} bytes for {} nodes", art_heap, arena_heap, nodes);
+}
+
+// u64 ids, dense in the low bytes as ids handed out in order are
+fn bench_u64() {
+    let mut seed = 0x9e3779b97f4a7c15u64;
+    let ids: Vec<u64> = (0..N).map(|_| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed % (4 * N as u64)
+    }).collect();
+    println!("{} u64 ids", N);
+
+    let start = Instant::now();
+    let mut art = ArtU64::new();
+    for &id in ids.iter() {
+        art.insert(id, id);
+    }
+    report("art_u64 insert", start, N);
+    let start = Instant::now();
+    for &id in ids.iter() {
+        assert!(black_box(&art).search(id).is_some());
+    }
+    report("art_u64 search", start, N);
+    let start = Instant::now();
+    for batch in ids.chunks(1024) {
+        assert!(black_box(&art).get_many(batch).iter().all(|v| v.is_some()));
+    }
+    report("art_u64 get_many", start, N);
+    // batches of neighbouring ids share most of their path
+    let mut near = ids.clone();
+    near.sort();
+    let start = Instant::now();
+    for &id in near.iter() {
+        assert!(black_box(&art).search(id).is_some());
+    }
+    report("art_u64 search near", start, N);
+    let start = Instant::now();
+    for batch in near.chunks(1024) {
+        assert!(black_box(&art).get_many(batch).iter().all(|v| v.is_some()));
+    }
+    report("art_u64 get_many near", start, N);
+
+    let mut bytes = Art::new();
+    for &id in ids.iter() {
+        bytes.insert(&id.to_be_bytes(), id);
+    }
+    let start = Instant::now();
+    for &id in ids.iter() {
+        assert!(black_box(&bytes).search(&id.to_be_bytes()).is_some());
+    }
+    report("art be bytes search", start, N);
+    drop(bytes);
+
+    let mut btree = BTreeMap::new();
+    let start = Instant::now();
+    for &id in ids.iter() {
+        btree.insert(id, id);
+    }
+    report("btreemap u64 insert", start, N);
+    let start = Instant::now();
+    for &id in ids.iter() {
+        assert!(black_box(&btree).get(&id).is_some());
+    }
+    report("btreemap u64 get", start, N);
+
+    let mut hash = HashMap::new();
+    let start = Instant::now();
+    for &id in ids.iter() {
+        hash.insert(id, id);
+    }
+    report("hashmap u64 insert", start, N);
+    let start = Instant::now();
+    for &id in ids.iter() {
+        assert!(black_box(&hash).get(&id).is_some());
+    }
+    report("hashmap u64 get", start, N);
+}
+
+fn main() {
+    let keys = gen_keys(N);
+    println!("{} keys", N);
+
+    let before = LIVE.load(Ordering::Relaxed);
+    let mut art = Art::new();
+    let start = Instant::now();
+    for k in keys.iter() {
+        art.insert(k, k.clone());
+    }
+    report("art insert", start, N);
+    let built = LIVE.load(Ordering::Relaxed);
+
+    let start = Instant::now();
+    let mut found = 0usize;
+    for k in keys.iter() {
+        if art.search(k).is_some() {
+            found += 1;
+        }
+    }
+    let art_ns = start.elapsed().as_nanos() as f64 / N as f64;
+    report("art search", start, N);
+    assert_eq!(found, N);
+
+    // the Rc<RefCell<dyn ArtNode>> Art it replaced
+    let mut old = art_rc::Art::new();
+    let start = Instant::now();
+    for k in keys.iter() {
+        old.insert(k.clone(), k.clone());
+    }
+    report("rc art insert", start, N);
+    let start = Instant::now();
+    for k in keys.iter() {
+        assert!(black_box(&old).search(k).is_some());
+    }
+    let old_ns = start.elapsed().as_nanos() as f64 / N as f64;
+    report("rc art search", start, N);
+    println!("art search is {:.1}x the rc art, {} nodes against {}", old_ns / art_ns,
+             art.get_size(), old.get_size());
+    drop(old);
+
+    let mut btree: BTreeMap<String, String> = BTreeMap::new();
+    let mut hash: HashMap<String, String> = HashMap::new();
+    for k in keys.iter() {
+        btree.insert(k.clone(), k.clone());
+        hash.insert(k.clone(), k.clone());
+    }
+    let start = Instant::now();
+    for k in keys.iter() {
+        assert!(btree.contains_key(k));
+    }
+    report("btreemap get", start, N);
+    let start = Instant::now();
+    for k in keys.iter() {
+        assert!(hash.contains_key(k));
+    }
+    report("hashmap get", start, N);
+    drop(btree);
+    drop(hash);
+
+    // the same keys sorted, inserted one by one and bulk loaded
+    let mut sorted = keys.clone();
+    sorted.sort();
+    sorted.dedup();
+    let start = Instant::now();
+    let mut art2 = Art::new();
+    for k in sorted.iter() {
+        art2.insert(k, ());
+    }
+    report("art sorted insert", start, sorted.len());
+    let start = Instant::now();
+    let art3 = Art::bulk_load(sorted.iter().map(|k| (k, ()))).unwrap();
+    report("art bulk_load", start, sorted.len());
+    assert_eq!(art2.get_size(), art3.get_size());
+    drop(art2);
+    drop(art3);
+    drop(sorted);
+
+    println!("art nodes: {}, heap: {} bytes", art.get_size(), built - before);
+    drop(art);
+    let after = LIVE.load(Ordering::Relaxed);
+    println!("heap not reclaimed after drop: {} bytes", after as isize - before as isize);
+
+    bench_arena(&keys);
+    bench_u64();
+    bench_node16();
+}
@@ -0,0 +1,201 @@
+// Bounded cache on Art.
+//
+// Every key has a rank, the key with the lowest rank is evicted first when the
+// cache holds more entries or bytes than its budget. With Lru the rank is the
+// tick of the last use, with Lfu the number of uses and then the tick. Ranks
+// are kept in a BTreeMap next to the tree. Evicted keys go through
+// Art::delete, so their nodes are pruned and shrunk like any other delete.
+
+use std::collections::BTreeMap;
+use std::mem;
+
+use crate::art::{Art, ArtConfig, InvalidTree, Stats};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eviction {
+    // least recently used
+    Lru,
+    // least frequently used, the least recently used of those first
+    Lfu,
+}
+
+pub struct CacheConfig<V> {
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    pub eviction: Eviction,
+    // bytes an entry counts for
+    pub weigh: fn(&[u8], &V) -> usize,
+    pub tree: ArtConfig,
+}
+
+fn default_weigh<V>(key: &[u8], _: &V) -> usize {
+    key.len() + mem::size_of::<V>()
+}
+
+// no budget, set max_entries or max_bytes
+impl<V> Default for CacheConfig<V> {
+    fn default() -> CacheConfig<V> {
+        CacheConfig {
+            max_entries: usize::MAX,
+            max_bytes: usize::MAX,
+            eviction: Eviction::Lru,
+            weigh: default_weigh::<V>,
+            tree: ArtConfig::default(),
+        }
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    // (uses, tick), uses stays 0 with Lru
+    rank: (u64, u64),
+    bytes: usize,
+}
+
+pub struct ArtCache<V> {
+    art: Art<Entry<V>>,
+    ranks: BTreeMap<(u64, u64), Vec<u8>>,
+    tick: u64,
+    bytes: usize,
+    evictions: u64,
+    max_entries: usize,
+    max_bytes: usize,
+    eviction: Eviction,
+    weigh: fn(&[u8], &V) -> usize,
+    // for clear
+    tree: ArtConfig,
+}
+
+impl<V> ArtCache<V> {
+    pub fn new(config: CacheConfig<V>) -> ArtCache<V> {
+        ArtCache {
+            art: Art::with_config(config.tree.clone()),
+            ranks: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            evictions: 0,
+            max_entries: config.max_entries,
+            max_bytes: config.max_bytes,
+            eviction: config.eviction,
+            weigh: config.weigh,
+            tree: config.tree,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.art.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.art.is_empty()
+    }
+
+    // bytes of all entries, as counted by weigh
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // keys evicted so far
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    // stats and validate of the tree
+    pub fn stats(&self) -> Stats {
+        self.art.stats()
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidTree> {
+        self.art.validate()
+    }
+
+    // the rank after one more use
+    fn next_rank(&mut self, rank: Option<(u64, u64)>) -> (u64, u64) {
+        self.tick += 1;
+        match (self.eviction, rank) {
+            (Eviction::Lfu, Some((uses, _))) => (uses + 1, self.tick),
+            (Eviction::Lfu, None) => (1, self.tick),
+            (Eviction::Lru, _) => (0, self.tick),
+        }
+    }
+
+    // insert or replace, then evict down to the budget. The new key is used
+    // last, it is evicted only when it does not fit on its own.
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, val: V) -> Option<V> {
+        let key = key.as_ref();
+        let bytes = (self.weigh)(key, &val);
+        let old = self.art.search(key).map(|e| e.rank);
+        let rank = self.next_rank(old);
+        if let Some(old) = old {
+            self.ranks.remove(&old);
+        }
+        self.ranks.insert(rank, key.to_vec());
+        let prev = self.art.insert(key, Entry {
+            value: val,
+            rank,
+            bytes,
+        });
+        self.bytes += bytes;
+        let prev = prev.map(|e| {
+            self.bytes -= e.bytes;
+            e.value
+        });
+        self.evict();
+        prev
+    }
+
+    fn evict(&mut self) {
+        while self.art.len() > self.max_entries || self.bytes > self.max_bytes {
+            let rank = match self.ranks.keys().next() {
+                Some(&rank) => rank,
+                None => break,
+            };
+            let key = self.ranks.remove(&rank).unwrap();
+            let e = self.art.delete(&key).unwrap();
+            self.bytes -= e.bytes;
+            self.evictions += 1;
+        }
+    }
+
+    // the value of key, which counts as a use
+    pub fn get<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<&V> {
+        let key = key.as_ref();
+        let old = self.art.search(key)?.rank;
+        let rank = self.next_rank(Some(old));
+        let k = self.ranks.remove(&old).unwrap();
+        self.ranks.insert(rank, k);
+        let e = self.art.search_mut(key).unwrap();
+        e.rank = rank;
+        Some(&e.value)
+    }
+
+    // the value of key without using it
+    pub fn peek<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&V> {
+        self.art.search(key).map(|e| &e.value)
+    }
+
+    pub fn remove<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<V> {
+        let e = self.art.delete(key)?;
+        self.ranks.remove(&e.rank);
+        self.bytes -= e.bytes;
+        Some(e.value)
+    }
+
+    // change the budget, evicting down to it at once
+    pub fn set_budget(&mut self, max_entries: usize, max_bytes: usize) {
+        self.max_entries = max_entries;
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    pub fn clear(&mut self) {
+        self.art = Art::with_config(self.tree.clone());
+        self.ranks.clear();
+        self.bytes = 0;
+    }
+
+    // keys and values from the next to be evicted on
+    pub fn iter_by_rank<'a>(&'a self) -> impl Iterator<Item = (&'a [u8], &'a V)> + 'a {
+        self.ranks.values().map(move |k| (k.as_slice(), &self.art.search(k).unwrap().value))
+    }
+}
@@ -0,0 +1,277 @@
+#[allow(dead_code)]
+mod art;
+#[allow(dead_code)]
+mod ttl;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use art::{Art, ArtConfig, DelayedShrink, NodeKind, ResizePolicy, Thresholds};
+use ttl::{ManualClock, TtlArt};
+
+// Checks of the resize policies of Art: a node must shrink at the number of
+// children its policy says and no other, and the tree must stay valid. Then
+// checks of TtlArt on a ManualClock.
+// Build: rustc checks.rs
+
+fn config<P: ResizePolicy + 'static>(policy: P) -> ArtConfig {
+    ArtConfig { resize: Arc::new(policy) }
+}
+
+// the types of the inner nodes of a tree, Node4 to Node256
+fn kinds<V>(art: &Art<V>) -> [usize; 4] {
+    let s = art.stats();
+    [s.node4, s.node16, s.node48, s.node256]
+}
+
+// the type of the only inner node of a tree
+fn kind<V>(art: &Art<V>) -> NodeKind {
+    match kinds(art) {
+        [1, 0, 0, 0] => NodeKind::N4,
+        [0, 1, 0, 0] => NodeKind::N16,
+        [0, 0, 1, 0] => NodeKind::N48,
+        [0, 0, 0, 1] => NodeKind::N256,
+        k => panic!("not a single inner node: {:?}", k),
+    }
+}
+
+// a node with children 0 to n - 1 under the path of b
+fn add_node(art: &mut Art<usize>, b: u8, n: usize) {
+    for c in 0..n {
+        art.insert(&[b, c as u8], c);
+    }
+}
+
+fn node_of(config: ArtConfig, n: usize) -> Art<usize> {
+    let mut art = Art::with_config(config);
+    add_node(&mut art, b'x', n);
+    art
+}
+
+// delete children from - 1 down to to of the node under b
+fn delete_down(art: &mut Art<usize>, b: u8, from: usize, to: usize) {
+    for c in (to..from).rev() {
+        assert_eq!(art.delete(&[b, c as u8]), Some(c));
+        art.validate().unwrap();
+    }
+}
+
+// Delete the children of a node of n one at a time, the last first, and
+// return how many are left when it is no longer of type big.
+fn shrinks_at(config: ArtConfig, n: usize, big: NodeKind) -> Option<usize> {
+    let mut art = node_of(config, n);
+    assert_eq!(kind(&art), big);
+    for left in (2..n).rev() {
+        delete_down(&mut art, b'x', left + 1, left);
+        if kind(&art) != big {
+            return Some(left);
+        }
+    }
+    None
+}
+
+fn thresholds() {
+    let d = || config(Thresholds::default());
+    assert_eq!(shrinks_at(d(), 16, NodeKind::N16), Some(2));
+    assert_eq!(shrinks_at(d(), 48, NodeKind::N48), Some(14));
+    assert_eq!(shrinks_at(d(), 256, NodeKind::N256), Some(45));
+
+    let high = || config(Thresholds::new(5, 17, 49));
+    assert_eq!(shrinks_at(high(), 16, NodeKind::N16), Some(4));
+    assert_eq!(shrinks_at(high(), 48, NodeKind::N48), Some(16));
+    assert_eq!(shrinks_at(high(), 256, NodeKind::N256), Some(48));
+
+    let low = || config(Thresholds::new(0, 4, 10));
+    assert_eq!(shrinks_at(low(), 16, NodeKind::N16), None);
+    assert_eq!(shrinks_at(low(), 48, NodeKind::N48), Some(3));
+    assert_eq!(shrinks_at(low(), 256, NodeKind::N256), Some(9));
+}
+
+fn delayed_shrink() {
+    let delayed = DelayedShrink { policy: Thresholds::default(), deletes: 8 };
+
+    // deletes are counted per node: 6 in one Node48 and 7 in the other
+    // shrink neither, 2 more in the first shrink it
+    let mut art = Art::with_config(config(delayed));
+    add_node(&mut art, b'a', 20);
+    add_node(&mut art, b'b', 20);
+    delete_down(&mut art, b'a', 20, 14);
+    delete_down(&mut art, b'b', 20, 13);
+    assert_eq!(kinds(&art), [1, 0, 2, 0]);
+    delete_down(&mut art, b'a', 14, 12);
+    assert_eq!(kinds(&art), [1, 1, 1, 0]);
+
+    // the count starts again in the smaller node: the Node48 a Node256
+    // shrinks into is under its threshold at once but waits for 8 deletes
+    let policy = DelayedShrink { policy: Thresholds::new(3, 17, 18), deletes: 8 };
+    let mut art = node_of(config(policy), 49);
+    delete_down(&mut art, b'x', 49, 18);
+    assert_eq!(kind(&art), NodeKind::N256);
+    delete_down(&mut art, b'x', 18, 17);
+    assert_eq!(kind(&art), NodeKind::N48);
+    delete_down(&mut art, b'x', 17, 10);
+    assert_eq!(kind(&art), NodeKind::N48);
+    delete_down(&mut art, b'x', 10, 9);
+    assert_eq!(kind(&art), NodeKind::N16);
+}
+
+fn delete_range() {
+    let cases: Vec<(ArtConfig, NodeKind)> = vec![
+        (config(Thresholds::default()), NodeKind::N16),
+        (config(Thresholds::new(3, 5, 46)), NodeKind::N48),
+        (config(DelayedShrink { policy: Thresholds::default(), deletes: 50 }), NodeKind::N48),
+        (config(DelayedShrink { policy: Thresholds::default(), deletes: 30 }), NodeKind::N16),
+    ];
+    for (config, expect) in cases {
+        let mut art = node_of(config, 40);
+        assert_eq!(art.delete_range(&[b'x', 0][..]..&[b'x', 30][..]), 30);
+        art.validate().unwrap();
+        assert_eq!(kind(&art), expect);
+    }
+
+    // one delete_range may shrink a node more than once
+    let mut art = node_of(config(Thresholds::default()), 100);
+    assert_eq!(art.delete_prefix(&[b'x', 2][..]), 1);
+    assert_eq!(art.delete_range(&[b'x', 3][..]..), 97);
+    art.validate().unwrap();
+    assert_eq!(kind(&art), NodeKind::N4);
+}
+
+// shrinks whenever it is asked
+struct Always;
+
+impl ResizePolicy for Always {
+    fn shrink(&self, _: NodeKind, _: usize, _: usize) -> bool {
+        true
+    }
+}
+
+struct Never;
+
+impl ResizePolicy for Never {
+    fn shrink(&self, _: NodeKind, _: usize, _: usize) -> bool {
+        false
+    }
+}
+
+// a policy which says shrink too early waits until the children fit
+fn custom() {
+    assert_eq!(shrinks_at(config(Always), 16, NodeKind::N16), Some(4));
+    assert_eq!(shrinks_at(config(Always), 48, NodeKind::N48), Some(16));
+    assert_eq!(shrinks_at(config(Always), 256, NodeKind::N256), Some(48));
+    assert_eq!(shrinks_at(config(Never), 256, NodeKind::N256), None);
+
+    let mut art = node_of(config(Always), 256);
+    assert_eq!(art.delete_range(&[b'x', 20][..]..), 236);
+    art.validate().unwrap();
+    assert_eq!(kind(&art), NodeKind::N48);
+    delete_down(&mut art, b'x', 20, 4);
+    assert_eq!(kind(&art), NodeKind::N4);
+}
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+fn ttl_art() -> (Arc<ManualClock>, TtlArt<usize, Arc<ManualClock>>) {
+    let clock = Arc::new(ManualClock::new());
+    (clock.clone(), TtlArt::with_clock(clock))
+}
+
+// expired keys read as missing, get and get_mut delete them, peek does not
+fn lazy_expiry() {
+    let (clock, mut t) = ttl_art();
+    t.insert_with_ttl("a", 1, secs(10));
+    t.insert_with_ttl("b", 2, secs(10));
+    t.insert("c", 3);
+    clock.advance(secs(9));
+    *t.get_mut("b").unwrap() += 10;
+    assert_eq!(t.get("b"), Some(&12));
+    clock.advance(secs(1));
+    assert_eq!(t.peek("a"), None);
+    assert_eq!((t.len(), t.expiring()), (3, 2));
+    assert_eq!(t.get("a"), None);
+    assert_eq!((t.len(), t.expiring()), (2, 1));
+    assert_eq!(t.get_mut("b"), None);
+    assert_eq!((t.len(), t.expiring()), (1, 0));
+    assert_eq!(t.get("c"), Some(&3));
+    assert_eq!(t.iter().count(), 1);
+}
+
+fn purge_budget() {
+    let (clock, mut t) = ttl_art();
+    for i in 0..10 {
+        t.insert_with_ttl(&[i as u8], i, secs(i as u64 + 1));
+    }
+    t.insert("forever", 10);
+    assert_eq!(t.purge_expired(100), 0);
+    clock.advance(secs(5));
+    assert_eq!(t.purge_expired(3), 3);
+    assert_eq!(t.purge_expired(3), 2);
+    assert_eq!(t.purge_expired(3), 0);
+    assert_eq!((t.len(), t.expiring()), (6, 5));
+    // the earliest deadlines go first
+    assert_eq!(t.peek(&[5u8]), Some(&5));
+    clock.advance(secs(100));
+    assert_eq!(t.purge_expired(0), 0);
+    assert_eq!(t.purge_expired(100), 5);
+    assert_eq!((t.len(), t.expiring()), (1, 0));
+}
+
+fn replace_ttl() {
+    let (clock, mut t) = ttl_art();
+    // insert drops the ttl
+    t.insert_with_ttl("k", 1, secs(10));
+    assert_eq!(t.insert("k", 2), Some(1));
+    assert_eq!(t.expiring(), 0);
+    clock.advance(secs(20));
+    assert_eq!(t.get("k"), Some(&2));
+
+    // a new ttl replaces the old deadline
+    t.insert_with_ttl("k", 3, secs(10));
+    assert_eq!(t.insert_with_ttl("k", 4, secs(30)), Some(3));
+    assert_eq!(t.expiring(), 1);
+    clock.advance(secs(15));
+    assert_eq!(t.purge_expired(10), 0);
+    assert_eq!(t.get("k"), Some(&4));
+
+    // an expired value is not returned
+    clock.advance(secs(15));
+    assert_eq!(t.insert("k", 5), None);
+    assert_eq!((t.len(), t.expiring()), (1, 0));
+}
+
+fn ttl_left() {
+    let (clock, mut t) = ttl_art();
+    t.insert_with_ttl("k", 1, secs(60));
+    t.insert("p", 2);
+    clock.advance(secs(15));
+    assert_eq!(t.ttl("k"), Some(secs(45)));
+    assert_eq!(t.ttl("p"), None);
+    assert_eq!(t.ttl("missing"), None);
+    clock.advance(secs(45));
+    assert_eq!(t.ttl("k"), None);
+    assert_eq!(t.get("k"), None);
+
+    // a deadline past the largest Duration never comes
+    t.insert_with_ttl("far", 3, Duration::MAX);
+    assert_eq!((t.ttl("far"), t.expiring()), (None, 0));
+    // the clock stops at its largest time
+    clock.advance(Duration::MAX);
+    clock.advance(secs(1));
+    assert_eq!(t.get("far"), Some(&3));
+    assert_eq!(t.purge_expired(10), 0);
+    assert_eq!(t.len(), 2);
+}
+
+fn main() {
+    thresholds();
+    delayed_shrink();
+    delete_range();
+    custom();
+    lazy_expiry();
+    purge_budget();
+    replace_ttl();
+    ttl_left();
+    println!("checks passed");
+}
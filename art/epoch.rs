@@ -0,0 +1,134 @@
+// Epoch based reclamation for the concurrent trees.
+//
+// A node unlinked by a writer may still be read by threads which found it
+// before. Readers and writers pin the collector for the time of an operation,
+// unlinked objects are retired with the global epoch of that moment and freed
+// once the epoch moved on twice: every thread pinned then has unpinned since.
+
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// at most this many threads are pinned at once, more wait for a free slot
+const SLOTS: usize = 128;
+// unpins between two tries to free garbage
+const COLLECT_EVERY: usize = 64;
+
+// an object to free, with the drop function of its type
+struct Deferred {
+    ptr: *mut u8,
+    drop: unsafe fn(*mut u8),
+}
+
+// only used to move the object to the thread which frees it
+unsafe impl Send for Deferred {}
+
+unsafe fn drop_box<T>(ptr: *mut u8) {
+    drop(Box::from_raw(ptr as *mut T));
+}
+
+pub struct Collector {
+    epoch: AtomicUsize,
+    // 0 for a free slot, else the epoch seen by the pinned thread << 1 | 1
+    slots: Vec<AtomicUsize>,
+    unpins: AtomicUsize,
+    // retired objects with the epoch they were retired in
+    garbage: Mutex<Vec<(usize, Deferred)>>,
+}
+
+pub struct Guard<'a> {
+    collector: &'a Collector,
+    slot: usize,
+}
+
+impl Collector {
+    pub fn new() -> Collector {
+        Collector {
+            epoch: AtomicUsize::new(0),
+            slots: (0..SLOTS).map(|_| AtomicUsize::new(0)).collect(),
+            unpins: AtomicUsize::new(0),
+            garbage: Mutex::new(Vec::new()),
+        }
+    }
+
+    // objects reachable now are not freed before the guard is dropped
+    pub fn pin<'a>(&'a self) -> Guard<'a> {
+        loop {
+            let e = self.epoch.load(Ordering::SeqCst);
+            for (i, slot) in self.slots.iter().enumerate() {
+                if slot.compare_exchange(0, e << 1 | 1, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                    fence(Ordering::SeqCst);
+                    return Guard {
+                        collector: self,
+                        slot: i,
+                    };
+                }
+            }
+            thread::yield_now();
+        }
+    }
+
+    // advance the epoch if every pinned thread has seen it, then free what
+    // was retired two epochs ago
+    fn collect(&self) {
+        let e = self.epoch.load(Ordering::SeqCst);
+        let seen = self.slots.iter().all(|s| {
+            let v = s.load(Ordering::SeqCst);
+            v == 0 || v >> 1 == e
+        });
+        if seen {
+            let _ = self.epoch.compare_exchange(e, e + 1, Ordering::SeqCst, Ordering::Relaxed);
+        }
+
+        let now = self.epoch.load(Ordering::SeqCst);
+        let ready: Vec<Deferred> = {
+            let mut garbage = self.garbage.lock().unwrap();
+            let mut ready = Vec::new();
+            let mut i = 0usize;
+            while i < garbage.len() {
+                if garbage[i].0 + 2 <= now {
+                    ready.push(garbage.swap_remove(i).1);
+                } else {
+                    i += 1;
+                }
+            }
+            ready
+        };
+        for d in ready {
+            unsafe { (d.drop)(d.ptr) };
+        }
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        // nobody is pinned any more
+        let garbage = self.garbage.get_mut().unwrap();
+        for (_, d) in garbage.drain(..) {
+            unsafe { (d.drop)(d.ptr) };
+        }
+    }
+}
+
+impl<'a> Guard<'a> {
+    // free the box at ptr once no thread can read it, ptr must be unlinked
+    // from the structure already
+    pub unsafe fn defer_drop<T>(&self, ptr: *mut T) {
+        let c = self.collector;
+        let e = c.epoch.load(Ordering::SeqCst);
+        c.garbage.lock().unwrap().push((e, Deferred {
+            ptr: ptr as *mut u8,
+            drop: drop_box::<T>,
+        }));
+    }
+}
+
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        let c = self.collector;
+        c.slots[self.slot].store(0, Ordering::SeqCst);
+        if c.unpins.fetch_add(1, Ordering::Relaxed).is_multiple_of(COLLECT_EVERY) {
+            c.collect();
+        }
+    }
+}
@@ -0,0 +1,305 @@
+// Order-preserving key encoding for Art.
+//
+// Art compares keys as raw bytes, so typed keys are encoded into byte strings
+// whose lexicographic order is the logical order of the values:
+//
+//   unsigned ints   big-endian
+//   signed ints     big-endian with the sign bit flipped
+//   f32/f64         IEEE bits, sign bit flipped for positives, all bits for
+//                   negatives (the order of total_cmp)
+//   bool            0 or 1
+//   str/bytes       0x00 escaped as 0x00 0xff, terminated by 0x00 0x01
+//   Option<T>       0 for None, 1 followed by the value
+//   tuples          fields one after another
+//
+// Every encoding is self-delimiting, so composites sort field by field.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    // input ends in the middle of a value
+    UnexpectedEnd,
+    // byte which no encoding produces at this place
+    InvalidByte(u8),
+    InvalidUtf8,
+    // input left after the value
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of key"),
+            DecodeError::InvalidByte(b) => write!(f, "invalid byte 0x{:02x} in key", b),
+            DecodeError::InvalidUtf8 => write!(f, "string in key is not utf-8"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} bytes left after key", n),
+        }
+    }
+}
+
+pub trait KeyEncode {
+    fn encode_key(&self, out: &mut Vec<u8>);
+}
+
+pub trait KeyDecode: Sized {
+    // decode one value from the front of input and advance it
+    fn decode_key(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+pub fn encode<T: KeyEncode + ?Sized>(val: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    val.encode_key(&mut out);
+    out
+}
+
+// decode a value taking up the whole key
+pub fn decode<T: KeyDecode>(key: &[u8]) -> Result<T, DecodeError> {
+    let mut input = key;
+    let val = T::decode_key(&mut input)?;
+    if !input.is_empty() {
+        return Err(DecodeError::TrailingBytes(input.len()));
+    }
+    Ok(val)
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < n {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Ok(head)
+}
+
+macro_rules! unsigned_key {
+    ($($t:ty),*) => {$(
+        impl KeyEncode for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+        }
+
+        impl KeyDecode for $t {
+            fn decode_key(input: &mut &[u8]) -> Result<$t, DecodeError> {
+                let mut buf = [0u8; std::mem::size_of::<$t>()];
+                let n = buf.len();
+                buf.copy_from_slice(take(input, n)?);
+                Ok(<$t>::from_be_bytes(buf))
+            }
+        }
+    )*}
+}
+
+// the unsigned type of the same width carries the bits of the signed one
+macro_rules! signed_key {
+    ($($t:ty => $u:ty),*) => {$(
+        impl KeyEncode for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let bits = (*self as $u) ^ (1 << (<$u>::BITS - 1));
+                bits.encode_key(out);
+            }
+        }
+
+        impl KeyDecode for $t {
+            fn decode_key(input: &mut &[u8]) -> Result<$t, DecodeError> {
+                let bits = <$u>::decode_key(input)?;
+                Ok((bits ^ (1 << (<$u>::BITS - 1))) as $t)
+            }
+        }
+    )*}
+}
+
+macro_rules! float_key {
+    ($($t:ty => $u:ty),*) => {$(
+        impl KeyEncode for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let bits = self.to_bits();
+                let sign = 1 << (<$u>::BITS - 1);
+                let bits = if bits & sign != 0 { !bits } else { bits ^ sign };
+                bits.encode_key(out);
+            }
+        }
+
+        impl KeyDecode for $t {
+            fn decode_key(input: &mut &[u8]) -> Result<$t, DecodeError> {
+                let bits = <$u>::decode_key(input)?;
+                let sign = 1 << (<$u>::BITS - 1);
+                let bits = if bits & sign != 0 { bits ^ sign } else { !bits };
+                Ok(<$t>::from_bits(bits))
+            }
+        }
+    )*}
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+float_key!(f32 => u32, f64 => u64);
+
+impl KeyEncode for bool {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl KeyDecode for bool {
+    fn decode_key(input: &mut &[u8]) -> Result<bool, DecodeError> {
+        match take(input, 1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(DecodeError::InvalidByte(b)),
+        }
+    }
+}
+
+impl KeyEncode for [u8] {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for b in self.iter() {
+            out.push(*b);
+            if *b == 0 {
+                out.push(0xff);
+            }
+        }
+        out.push(0);
+        out.push(1);
+    }
+}
+
+impl KeyDecode for Vec<u8> {
+    fn decode_key(input: &mut &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let mut v = Vec::new();
+        loop {
+            let b = take(input, 1)?[0];
+            if b != 0 {
+                v.push(b);
+                continue;
+            }
+            match take(input, 1)?[0] {
+                0xff => v.push(0),
+                1 => return Ok(v),
+                b => return Err(DecodeError::InvalidByte(b)),
+            }
+        }
+    }
+}
+
+impl KeyEncode for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_key(out);
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyDecode for String {
+    fn decode_key(input: &mut &[u8]) -> Result<String, DecodeError> {
+        let v = Vec::<u8>::decode_key(input)?;
+        String::from_utf8(v).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: KeyEncode + ?Sized> KeyEncode for &T {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (**self).encode_key(out);
+    }
+}
+
+impl<T: KeyEncode> KeyEncode for Option<T> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        match *self {
+            Some(ref v) => {
+                out.push(1);
+                v.encode_key(out);
+            },
+            None => out.push(0),
+        }
+    }
+}
+
+impl<T: KeyDecode> KeyDecode for Option<T> {
+    fn decode_key(input: &mut &[u8]) -> Result<Option<T>, DecodeError> {
+        match take(input, 1)?[0] {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_key(input)?)),
+            b => Err(DecodeError::InvalidByte(b)),
+        }
+    }
+}
+
+macro_rules! tuple_key {
+    ($(($($name:ident : $idx:tt),+)),*) => {$(
+        impl<$($name: KeyEncode),+> KeyEncode for ($($name,)+) {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                $(self.$idx.encode_key(out);)+
+            }
+        }
+
+        impl<$($name: KeyDecode),+> KeyDecode for ($($name,)+) {
+            fn decode_key(input: &mut &[u8]) -> Result<($($name,)+), DecodeError> {
+                Ok(($($name::decode_key(input)?,)+))
+            }
+        }
+    )*}
+}
+
+tuple_key!(
+    (A: 0),
+    (A: 0, B: 1),
+    (A: 0, B: 1, C: 2),
+    (A: 0, B: 1, C: 2, D: 3),
+    (A: 0, B: 1, C: 2, D: 3, E: 4),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5)
+);
+
+// a key made of fields of different types, for composites without a tuple
+pub struct KeyBuilder {
+    buf: Vec<u8>,
+}
+
+impl KeyBuilder {
+    pub fn new() -> KeyBuilder {
+        KeyBuilder {
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn push<T: KeyEncode + ?Sized>(mut self, val: &T) -> KeyBuilder {
+        val.encode_key(&mut self.buf);
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+// reads the fields of a composite key one by one
+pub struct KeyReader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> KeyReader<'a> {
+    pub fn new(key: &'a [u8]) -> KeyReader<'a> {
+        KeyReader {
+            input: key,
+        }
+    }
+
+    pub fn read<T: KeyDecode>(&mut self) -> Result<T, DecodeError> {
+        T::decode_key(&mut self.input)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+}
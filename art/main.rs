@@ -1,57 +1,213 @@
+#[allow(dead_code)]
 mod art;
+#[allow(dead_code)]
+mod cache;
+#[allow(dead_code)]
+mod keycodec;
+#[allow(dead_code)]
+mod morton;
+#[allow(dead_code)]
+mod routing;
+#[allow(dead_code)]
+mod text;
+#[allow(dead_code)]
+mod ttl;
 
-use art::Art;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::time::Duration;
+
+use art::{Art, ArtConfig, DelayedShrink, DotOptions, Thresholds};
+use cache::{ArtCache, CacheConfig, Eviction};
+use morton::MortonIndex;
+use routing::{Cidr, RoutingTable};
+use text::TextIndex;
+use ttl::{ManualClock, TtlArt};
 
 
 fn main() {
-    let mut art = Art::new();
+    let mut art: Art<Vec<String>> = Art::new();
 
     /*
-    art.insert(String::from("hello"), String::from("art"));
-    art.insert(String::from("hello"), String::from("art"));
+    art.push("hello", String::from("art"));
+    art.push("hello", String::from("art"));
 
-    search(&art, &String::from("hello"));
+    search(&art, "hello");
     println!("After insert two keys, Art node size: {}", art.get_size());
 
-    art.delete(&String::from("hello"));
-    search(&art, &String::from("hello"));
+    art.delete("hello");
+    search(&art, "hello");
     println!("After delete keys, Art node size: {}", art.get_size());
     */
 
     // make node expand
-    art.insert(String::from("hello"), String::from("hello"));
-    art.insert(String::from("helmo"), String::from("helmo"));
-    art.insert(String::from("helno"), String::from("helno"));
-    art.insert(String::from("heloo"), String::from("heloo"));
-    art.insert(String::from("helpo"), String::from("helpo"));
-    art.insert(String::from("helqo"), String::from("helqo"));
-    
-    search(&art, &String::from("hello"));
-    search(&art, &String::from("helmo"));
-    search(&art, &String::from("helno"));
-    search(&art, &String::from("heloo"));
+    art.push("hello", String::from("hello"));
+    art.push("helmo", String::from("helmo"));
+    art.push("helno", String::from("helno"));
+    art.push("heloo", String::from("heloo"));
+    art.push("helpo", String::from("helpo"));
+    art.push("helqo", String::from("helqo"));
+    write_dot(&art, "art_expand.dot", &DotOptions::default());
     println!("After node expand, Art node size: {}", art.get_size());
 
+    println!("Keys from helno on:");
+    for (k, vals) in art.range("helno"..) {
+        println!("{} => {:?}", String::from_utf8_lossy(k), vals);
+    }
+    if let Some((k, _)) = art.predecessor("helno") {
+        println!("Key before helno: {}", String::from_utf8_lossy(k));
+    }
+    println!("Number of keys starting with hel: {}", art.prefix_iter("hel").count());
+
+    let before = art.snapshot();
 
-    art.delete(&String::from("hello"));
-    art.delete(&String::from("helmo"));
-    art.delete(&String::from("helno"));
-    art.delete(&String::from("heloo"));
-    art.delete(&String::from("helpo"));
-    search(&art, &String::from("helqo"));
+    art.delete("hello");
+    art.delete("helmo");
+    art.delete("helno");
+    art.delete("heloo");
+    art.delete("helpo");
+    write_dot(&art, "art_shrink.dot", &DotOptions::default());
     println!("After node shrink, Art node size: {}", art.get_size());
+    match art.validate() {
+        Ok(()) => println!("Tree is valid\n{}", art.stats()),
+        Err(e) => println!("Invalid tree: {}", e),
+    }
+    // a node shrinks only after 8 of its children are deleted
+    let config = ArtConfig {
+        resize: Arc::new(DelayedShrink { policy: Thresholds::default(), deletes: 8 }),
+    };
+    let mut ids: Art<u8> = Art::with_config(config);
+    for c in 0..20u8 {
+        ids.insert(&[b'x', c], c);
+    }
+    for c in 0..6u8 {
+        ids.delete(&[b'x', c]);
+    }
+    println!("Node48 with {} children kept: {}", ids.len(), ids.stats().node48 == 1);
+    println!("Snapshot from before the deletes still has {} keys, hello => {:?}",
+             before.iter().count(), before.search("hello"));
+
+    let sorted = vec![("apple", 1), ("apply", 2), ("banana", 3), ("band", 4)];
+    let fruits = Art::bulk_load(sorted).unwrap();
+    println!("Bulk loaded {} nodes, band => {:?}", fruits.get_size(), fruits.search("band"));
+    // only the keys starting with app
+    write_dot(&fruits, "art_fruits.dot", &DotOptions { max_depth: None, prefix: b"app".to_vec() });
+    println!("apply is key {} of {}, {} keys in [apply, band)", fruits.rank("apply"),
+             fruits.len(), fruits.count_range("apply".."band"));
+    // the second page of two keys
+    if let Some((k, _)) = fruits.select(2) {
+        let page: Vec<_> = fruits.range(k..).take(2).map(|(k, _)| String::from_utf8_lossy(k).into_owned()).collect();
+        println!("Page 2: {:?}", page);
+    }
+    if let Err(e) = Art::bulk_load(vec![("pear", 1), ("fig", 2)]) {
+        println!("Bulk load failed: {}", e);
+    }
+
+    // typed keys: events by (user id, timestamp), negative timestamps sort first
+    let mut events: Art<&str> = Art::new();
+    events.insert(&keycodec::encode(&(7u32, 1500i64)), "logout");
+    events.insert(&keycodec::encode(&(7u32, -20i64)), "signup");
+    events.insert(&keycodec::encode(&(7u32, 300i64)), "login");
+    events.insert(&keycodec::encode(&(8u32, 0i64)), "signup");
+    println!("Events of user 7:");
+    let from = keycodec::encode(&(7u32, i64::MIN));
+    let to = keycodec::encode(&(8u32, i64::MIN));
+    for (k, ev) in events.range(from.as_slice()..to.as_slice()) {
+        let (user, ts): (u32, i64) = keycodec::decode(k).unwrap();
+        println!("{} {} {}", user, ts, ev);
+    }
+    // count events per kind in place
+    let mut kinds: Art<usize> = Art::new();
+    for (_, ev) in events.iter() {
+        kinds.upsert(ev, |n| n.map_or(1, |n| n + 1));
+    }
+    kinds.get_or_insert_with("refund", || 0);
+    let swapped = kinds.compare_and_swap("signup", &2, 0).is_ok();
+    println!("signup swapped: {}, login => {:?}, refund => {:?}", swapped,
+             kinds.update("login", |n| *n), kinds.search("refund"));
+    // a replica which missed one write and took another
+    let mut replica = Art::bulk_load(kinds.iter().map(|(k, n)| (k.to_vec(), *n))).unwrap();
+    replica.update("login", |n| *n += 1);
+    replica.insert("refund", 1);
+    let differ: Vec<_> = kinds.diff(&replica).iter().map(|k| String::from_utf8_lossy(k).into_owned()).collect();
+    println!("Replicas differ at {:?}", differ);
+    // drop all events of user 7 at once
+    let removed = events.delete_prefix(&keycodec::encode(&7u32));
+    println!("Removed {} events of user 7, {} left", removed, events.len());
+
+    // routes of any prefix length, the longest match wins
+    let mut routes: RoutingTable<&str> = RoutingTable::new();
+    for (cidr, hop) in [("10.0.0.0/8", "core"), ("10.1.0.0/16", "east"),
+                            ("10.1.128.0/17", "east-b"), ("2001:db8::/32", "v6")] {
+        routes.insert(cidr.parse().unwrap(), hop);
+    }
+    for addr in ["10.1.200.7", "10.1.5.5", "10.9.0.1", "2001:db8::1", "192.168.0.1"] {
+        let addr = addr.parse().unwrap();
+        let all: Vec<String> = routes.covering(addr).iter().map(|(c, _)| c.to_string()).collect();
+        match routes.lookup(addr) {
+            Some((cidr, hop)) => println!("{} via {} on {}, covered by {:?}", addr, hop, cidr, all),
+            None => println!("{} has no route", addr),
+        }
+    }
+    if let Err(e) = "10.0.0.0/33".parse::<Cidr>() {
+        println!("Bad route: {}", e);
+    }
+
+    // map tiles by (x, y) in a Morton index
+    let mut tiles: MortonIndex<String, 2> = MortonIndex::new();
+    for x in 0..8u32 {
+        for y in 0..8u32 {
+            tiles.insert([x, y], format!("tile-{}-{}", x, y));
+        }
+    }
+    let shown: Vec<String> = tiles.query_box([2, 3], [3, 4]).into_iter().map(|(_, t)| t.clone()).collect();
+    println!("Tiles in view: {:?}", shown);
+    let near: Vec<[u32; 2]> = tiles.nearest([10, 10], 3).into_iter().map(|(p, _)| p).collect();
+    println!("Tiles nearest to (10, 10): {:?}", near);
+
+    // full-text search over a few documents
+    let mut docs = TextIndex::new();
+    docs.add(1, "The adaptive radix tree is a trie with adaptive nodes");
+    docs.add(2, "A radix tree compresses paths, a B-tree keeps pages");
+    docs.add(3, "Tries answer prefix queries, hash tables do not");
+    for q in ["radix tree", "\"adaptive nodes\" OR hash", "tri* NOT adaptive", "(tree OR tables) AND NOT b"] {
+        match docs.query(q) {
+            Ok(ids) => println!("Documents matching {}: {:?}", q, ids),
+            Err(e) => println!("Bad query {}: {}", q, e),
+        }
+    }
+
+    // a cache of three users in front of a slow store
+    let mut users = ArtCache::new(CacheConfig { max_entries: 3, eviction: Eviction::Lfu, ..CacheConfig::default() });
+    for id in ["u1", "u2", "u1", "u3", "u4", "u1", "u5"] {
+        if users.get(id).is_none() {
+            users.insert(id, format!("user {} from the store", id));
+        }
+    }
+    let kept: Vec<_> = users.iter_by_rank().map(|(k, _)| String::from_utf8_lossy(k).into_owned()).collect();
+    println!("Cached users {:?} after {} evictions", kept, users.evictions());
+
+    // session tokens which expire, on a clock moved by hand
+    let clock = Arc::new(ManualClock::new());
+    let mut sessions = TtlArt::with_clock(clock.clone());
+    sessions.insert_with_ttl("token-a", "alice", Duration::from_secs(60));
+    sessions.insert_with_ttl("token-b", "bob", Duration::from_secs(300));
+    sessions.insert("token-root", "admin");
+    clock.advance(Duration::from_secs(120));
+    println!("After 2 minutes token-a => {:?}, token-b has {:?} left", sessions.peek("token-a"),
+             sessions.ttl("token-b"));
+    clock.advance(Duration::from_secs(600));
+    let purged = sessions.purge_expired(10);
+    println!("Purged {} sessions, {} left", purged, sessions.len());
 }
 
-fn search(art: &Art, key: &String) {
-    let res = art.search(key);
+// draw with: dot -Tsvg art_expand.dot -o art_expand.svg
+fn write_dot<V: fmt::Debug>(art: &Art<V>, path: &str, opts: &DotOptions) {
+    let res = File::create(path).and_then(|f| art.to_dot_with(BufWriter::new(f), opts));
     match res {
-        Some(ref vals) => {
-            println!("Found values for key:{} ", key);
-            for ele in vals {
-                println!("{}", ele);
-            }
-        },
-        None => println!("Key:{} not exists", key),
+        Ok(()) => println!("Tree written to {}", path),
+        Err(e) => println!("Cannot write {}: {}", path, e),
     }
 }
-
@@ -0,0 +1,234 @@
+// Z-order (Morton) spatial index on Art.
+//
+// The bits of the D coordinates of a point are interleaved from the highest
+// down into a Morton key of 32 * D bits, stored big endian in Art, so points
+// close in space mostly share a key prefix. Every prefix of the key is a cell,
+// a box with a power of two side in each dimension, whose points are one key
+// range. A box query splits the box into cells and scans their ranges, a
+// nearest neighbour search visits the cells closest to the point first.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::art::Art;
+
+pub type Point<const D: usize> = [u32; D];
+
+// a box query splits cells until it has about this many ranges to scan
+const MAX_RANGES: usize = 64;
+// a nearest neighbour search scans a cell with this many points or less
+const SCAN_POINTS: usize = 8;
+
+pub struct MortonIndex<V, const D: usize> {
+    art: Art<V>,
+}
+
+// the cell of the first depth bits of key
+#[derive(Clone, Copy)]
+struct Cell {
+    key: u128,
+    depth: u32,
+}
+
+fn bits<const D: usize>() -> u32 {
+    32 * D as u32
+}
+
+fn encode<const D: usize>(p: &Point<D>) -> u128 {
+    let mut key = 0u128;
+    for b in (0..32).rev() {
+        for c in p.iter() {
+            key = key << 1 | (c >> b & 1) as u128;
+        }
+    }
+    key
+}
+
+fn decode<const D: usize>(key: u128) -> Point<D> {
+    let mut p = [0u32; D];
+    let mut i = bits::<D>();
+    for b in (0..32).rev() {
+        for c in p.iter_mut() {
+            i -= 1;
+            *c |= ((key >> i & 1) as u32) << b;
+        }
+    }
+    p
+}
+
+fn key_bytes<const D: usize>(key: u128) -> Vec<u8> {
+    key.to_be_bytes()[16 - 4 * D..].to_vec()
+}
+
+fn bytes_key(bytes: &[u8]) -> u128 {
+    bytes.iter().fold(0, |key, b| key << 8 | *b as u128)
+}
+
+fn inside<const D: usize>(p: &Point<D>, min: &Point<D>, max: &Point<D>) -> bool {
+    (0..D).all(|d| min[d] <= p[d] && p[d] <= max[d])
+}
+
+// squared euclidean distance
+fn dist<const D: usize>(a: &Point<D>, b: &Point<D>) -> u128 {
+    (0..D).map(|d| {
+        let x = (a[d] as i64 - b[d] as i64).unsigned_abs() as u128;
+        x * x
+    }).sum()
+}
+
+impl Cell {
+    fn root() -> Cell {
+        Cell { key: 0, depth: 0 }
+    }
+
+    // the first and last key in the cell
+    fn keys<const D: usize>(&self) -> (u128, u128) {
+        let free = bits::<D>() - self.depth;
+        let ones = if free == 128 { !0 } else { (1u128 << free) - 1 };
+        (self.key, self.key | ones)
+    }
+
+    // the lowest and highest corner
+    fn corners<const D: usize>(&self) -> (Point<D>, Point<D>) {
+        let (lo, hi) = self.keys::<D>();
+        (decode(lo), decode(hi))
+    }
+
+    fn split<const D: usize>(&self) -> [Cell; 2] {
+        let bit = 1u128 << (bits::<D>() - self.depth - 1);
+        let depth = self.depth + 1;
+        [Cell { key: self.key, depth }, Cell { key: self.key | bit, depth }]
+    }
+
+    // squared distance from p to the nearest point of the cell
+    fn dist<const D: usize>(&self, p: &Point<D>) -> u128 {
+        let (lo, hi) = self.corners::<D>();
+        let mut near = *p;
+        for d in 0..D {
+            near[d] = p[d].max(lo[d]).min(hi[d]);
+        }
+        dist(&near, p)
+    }
+}
+
+impl<V, const D: usize> MortonIndex<V, D> {
+    pub fn new() -> MortonIndex<V, D> {
+        // the key must fit in a u128
+        assert!(D >= 1 && D <= 4, "MortonIndex supports 1 to 4 dimensions");
+        MortonIndex { art: Art::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.art.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.art.is_empty()
+    }
+
+    pub fn insert(&mut self, p: Point<D>, val: V) -> Option<V> {
+        self.art.insert(&key_bytes::<D>(encode(&p)), val)
+    }
+
+    pub fn get(&self, p: &Point<D>) -> Option<&V> {
+        self.art.search(&key_bytes::<D>(encode(p)))
+    }
+
+    pub fn remove(&mut self, p: &Point<D>) -> Option<V> {
+        self.art.delete(&key_bytes::<D>(encode(p)))
+    }
+
+    // all points in the box from min to max, both included, in Morton order
+    pub fn query_box(&self, min: Point<D>, max: Point<D>) -> Vec<(Point<D>, &V)> {
+        let mut found = Vec::new();
+        if (0..D).any(|d| min[d] > max[d]) {
+            return found;
+        }
+        for (lo, hi) in self.box_ranges(&min, &max) {
+            let (lo, hi) = (key_bytes::<D>(lo), key_bytes::<D>(hi));
+            for (k, v) in self.art.range(lo.as_slice()..=hi.as_slice()) {
+                // ranges of cells only partly in the box hold points outside
+                let p = decode(bytes_key(k));
+                if inside(&p, &min, &max) {
+                    found.push((p, v));
+                }
+            }
+        }
+        found
+    }
+
+    // key ranges covering the box, sorted and merged. Cells inside the box
+    // are taken whole, the ones across its border are split until there are
+    // about MAX_RANGES of them and then taken whole as well.
+    fn box_ranges(&self, min: &Point<D>, max: &Point<D>) -> Vec<(u128, u128)> {
+        let mut ranges = Vec::new();
+        let mut border = vec![Cell::root()];
+        while !border.is_empty() && border[0].depth < bits::<D>()
+            && ranges.len() + border.len() * 2 <= MAX_RANGES {
+            let mut next = Vec::new();
+            for cell in border {
+                for half in cell.split::<D>().iter() {
+                    let (lo, hi) = half.corners::<D>();
+                    if (0..D).any(|d| hi[d] < min[d] || lo[d] > max[d]) {
+                        continue;
+                    }
+                    if inside(&lo, min, max) && inside(&hi, min, max) {
+                        ranges.push(half.keys::<D>());
+                    } else {
+                        next.push(*half);
+                    }
+                }
+            }
+            border = next;
+        }
+        ranges.extend(border.iter().map(|c| c.keys::<D>()));
+        ranges.sort();
+        let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+        for (lo, hi) in ranges {
+            match merged.last_mut() {
+                Some(last) if last.1 + 1 == lo => last.1 = hi,
+                _ => merged.push((lo, hi)),
+            }
+        }
+        merged
+    }
+
+    // the k points nearest to p, nearest first, ties in Morton order
+    pub fn nearest(&self, p: Point<D>, k: usize) -> Vec<(Point<D>, &V)> {
+        // cells to visit by distance, and the best k keys found as a max heap
+        let mut cells = BinaryHeap::new();
+        let mut best: BinaryHeap<(u128, u128)> = BinaryHeap::new();
+        if k > 0 {
+            cells.push(Reverse((0u128, 0u128, 0u32)));
+        }
+        while let Some(Reverse((d, key, depth))) = cells.pop() {
+            if best.len() == k && d > best.peek().unwrap().0 {
+                break;
+            }
+            let cell = Cell { key, depth };
+            let (lo, hi) = cell.keys::<D>();
+            let (lo, hi) = (key_bytes::<D>(lo), key_bytes::<D>(hi));
+            let n = self.art.count_range(lo.as_slice()..=hi.as_slice());
+            if n == 0 {
+                continue;
+            }
+            if n > SCAN_POINTS && depth < bits::<D>() {
+                for half in cell.split::<D>().iter() {
+                    cells.push(Reverse((half.dist(&p), half.key, half.depth)));
+                }
+                continue;
+            }
+            for (k2, _) in self.art.range(lo.as_slice()..=hi.as_slice()) {
+                let key = bytes_key(k2);
+                best.push((dist(&decode::<D>(key), &p), key));
+                if best.len() > k {
+                    best.pop();
+                }
+            }
+        }
+        best.into_sorted_vec().into_iter().map(|(_, key)| {
+            let bytes = key_bytes::<D>(key);
+            (decode(key), self.art.search(&bytes).unwrap())
+        }).collect()
+    }
+}
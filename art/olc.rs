@@ -0,0 +1,786 @@
+// Concurrent ART with optimistic lock coupling.
+//
+// Every inner node has a version word. Readers take no locks: they note the
+// version of a node, read it, and check the version did not move before they
+// trust what they read, restarting from the root otherwise. Writers read the
+// same way and turn the version of the node they change into a lock, plus the
+// parent when the node itself is replaced, so never more than two at a time.
+//
+// Leaves never change once they are in the tree, a new value is a new leaf.
+// Unlinked nodes, leaves and prefixes go to the epoch collector, so a thread
+// still reading them never sees freed memory.
+//
+// Unlike Art, a node left with a single inner child is not merged into its
+// parent, that would lock the child as well. Nodes left with one leaf are
+// still replaced by it, and nodes still shrink below the same fill as in Art
+// with Thresholds::default(). ArtConfig and its resize policies are not
+// supported: the shrink is decided before the node is locked, from a child
+// count read optimistically, and nodes keep no count of their deletes.
+
+use std::hint;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicPtr, AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use crate::epoch::{Collector, Guard};
+
+const OBSOLETE: u64 = 0b01;
+const LOCKED: u64 = 0b10;
+
+const N4: u8 = 0;
+const N16: u8 = 1;
+const N48: u8 = 2;
+const N256: u8 = 3;
+
+// A child word is 0 for none, a leaf pointer with the low bit set,
+// or a pointer to the header of an inner node.
+const LEAF_TAG: usize = 1;
+
+struct Leaf<V> {
+    key: Vec<u8>,
+    value: V,
+}
+
+// first field of every inner node, so a pointer to it is a node pointer
+#[repr(C)]
+struct Header<V> {
+    version: AtomicU64,
+    kind: u8,
+    count: AtomicU16,
+    // compressed path, replaced as a whole and never null
+    prefix: AtomicPtr<Vec<u8>>,
+    // the key which ends at this node
+    leaf: AtomicPtr<Leaf<V>>,
+}
+
+#[repr(C)]
+struct Node4<V> {
+    hdr: Header<V>,
+    keys: [AtomicU8; 4],
+    children: [AtomicUsize; 4],
+}
+
+#[repr(C)]
+struct Node16<V> {
+    hdr: Header<V>,
+    keys: [AtomicU8; 16],
+    children: [AtomicUsize; 16],
+}
+
+#[repr(C)]
+struct Node48<V> {
+    hdr: Header<V>,
+    // slot of the child in children plus one, 0 if none
+    index: [AtomicU8; 256],
+    children: [AtomicUsize; 48],
+}
+
+#[repr(C)]
+struct Node256<V> {
+    hdr: Header<V>,
+    children: [AtomicUsize; 256],
+}
+
+fn leaf_word<V>(leaf: *mut Leaf<V>) -> usize {
+    leaf as usize | LEAF_TAG
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let mut i = 0usize;
+    while i < a.len() && i < b.len() && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+fn header<V>(prefix: &[u8], kind: u8) -> Header<V> {
+    Header {
+        version: AtomicU64::new(0),
+        kind,
+        count: AtomicU16::new(0),
+        prefix: AtomicPtr::new(Box::into_raw(Box::new(prefix.to_vec()))),
+        leaf: AtomicPtr::new(ptr::null_mut()),
+    }
+}
+
+fn new_node<V>(kind: u8, prefix: &[u8]) -> *mut Header<V> {
+    match kind {
+        N4 => Box::into_raw(Box::new(Node4 {
+            hdr: header::<V>(prefix, kind),
+            keys: std::array::from_fn(|_| AtomicU8::new(0)),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+        N16 => Box::into_raw(Box::new(Node16 {
+            hdr: header::<V>(prefix, kind),
+            keys: std::array::from_fn(|_| AtomicU8::new(0)),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+        N48 => Box::into_raw(Box::new(Node48 {
+            hdr: header::<V>(prefix, kind),
+            index: std::array::from_fn(|_| AtomicU8::new(0)),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+        _ => Box::into_raw(Box::new(Node256 {
+            hdr: header::<V>(prefix, kind),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+    }
+}
+
+// free an inner node, not its children nor its leaf
+unsafe fn free_node<V>(n: *mut Header<V>) {
+    match (*n).kind {
+        N4 => drop(Box::from_raw(n as *mut Node4<V>)),
+        N16 => drop(Box::from_raw(n as *mut Node16<V>)),
+        N48 => drop(Box::from_raw(n as *mut Node48<V>)),
+        _ => drop(Box::from_raw(n as *mut Node256<V>)),
+    }
+}
+
+unsafe fn retire_node<V>(guard: &Guard, n: *mut Header<V>) {
+    match (*n).kind {
+        N4 => guard.defer_drop(n as *mut Node4<V>),
+        N16 => guard.defer_drop(n as *mut Node16<V>),
+        N48 => guard.defer_drop(n as *mut Node48<V>),
+        _ => guard.defer_drop(n as *mut Node256<V>),
+    }
+}
+
+// free a whole subtree, nobody else may use it
+unsafe fn free_tree<V>(ch: usize) {
+    if ch == 0 {
+        return;
+    }
+    if ch & LEAF_TAG != 0 {
+        drop(Box::from_raw((ch & !LEAF_TAG) as *mut Leaf<V>));
+        return;
+    }
+    let n = ch as *mut Header<V>;
+    for (_, c) in (*n).children() {
+        free_tree::<V>(c);
+    }
+    let leaf = (*n).leaf.load(Ordering::Relaxed);
+    if !leaf.is_null() {
+        drop(Box::from_raw(leaf));
+    }
+    free_node(n);
+}
+
+// hang a leaf on a node which is not published yet
+unsafe fn put_leaf<V>(n: &Header<V>, depth: usize, leaf: *mut Leaf<V>) {
+    let key = &(*leaf).key;
+    if depth == key.len() {
+        n.leaf.store(leaf, Ordering::Release);
+    } else {
+        n.add_child(key[depth], leaf_word(leaf));
+    }
+}
+
+impl<V> Drop for Header<V> {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(*self.prefix.get_mut())) };
+    }
+}
+
+impl<V> Header<V> {
+    // version lock
+
+    // version of an unlocked node, None to restart
+    fn read_lock(&self) -> Option<u64> {
+        let v = self.version.load(Ordering::Acquire);
+        if v & (LOCKED | OBSOLETE) != 0 {
+            return None;
+        }
+        Some(v)
+    }
+
+    // nothing changed since version v was read
+    fn check(&self, v: u64) -> bool {
+        fence(Ordering::Acquire);
+        self.version.load(Ordering::Relaxed) == v
+    }
+
+    // lock the node if it is still at version v
+    fn upgrade(&self, v: u64) -> bool {
+        if self.version.compare_exchange(v, v + LOCKED, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return false;
+        }
+        // the writes to the node after this must not be seen before the
+        // locked version, a reader would check against the old one
+        fence(Ordering::Release);
+        true
+    }
+
+    fn unlock(&self) {
+        self.version.fetch_add(LOCKED, Ordering::Release);
+    }
+
+    // unlock a node which was replaced, readers on it restart
+    fn unlock_obsolete(&self) {
+        self.version.fetch_add(LOCKED | OBSOLETE, Ordering::Release);
+    }
+
+    // node access, writes need the lock
+
+    unsafe fn prefix<'a>(&self) -> &'a [u8] {
+        &*self.prefix.load(Ordering::Acquire)
+    }
+
+    unsafe fn set_prefix(&self, prefix: &[u8], guard: &Guard) {
+        let p = Box::into_raw(Box::new(prefix.to_vec()));
+        let old = self.prefix.swap(p, Ordering::AcqRel);
+        guard.defer_drop(old);
+    }
+
+    fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed) as usize
+    }
+
+    unsafe fn as4(&self) -> &Node4<V> {
+        &*(self as *const Header<V> as *const Node4<V>)
+    }
+
+    unsafe fn as16(&self) -> &Node16<V> {
+        &*(self as *const Header<V> as *const Node16<V>)
+    }
+
+    unsafe fn as48(&self) -> &Node48<V> {
+        &*(self as *const Header<V> as *const Node48<V>)
+    }
+
+    unsafe fn as256(&self) -> &Node256<V> {
+        &*(self as *const Header<V> as *const Node256<V>)
+    }
+
+    // edges and children of Node4/Node16, the first count are in use
+    unsafe fn sorted(&self) -> (&[AtomicU8], &[AtomicUsize]) {
+        match self.kind {
+            N4 => (&self.as4().keys, &self.as4().children),
+            _ => (&self.as16().keys, &self.as16().children),
+        }
+    }
+
+    unsafe fn find_child(&self, c: u8) -> usize {
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.sorted();
+                let n = self.count().min(keys.len());
+                for i in 0..n {
+                    if keys[i].load(Ordering::Relaxed) == c {
+                        return children[i].load(Ordering::Acquire);
+                    }
+                }
+                0
+            },
+            N48 => {
+                let n = self.as48();
+                match n.index[c as usize].load(Ordering::Relaxed) {
+                    0 => 0,
+                    i => n.children[i as usize - 1].load(Ordering::Acquire),
+                }
+            },
+            _ => self.as256().children[c as usize].load(Ordering::Acquire),
+        }
+    }
+
+    // edges and children in key order
+    unsafe fn children(&self) -> Vec<(u8, usize)> {
+        let mut res = Vec::new();
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.sorted();
+                for i in 0..self.count() {
+                    res.push((keys[i].load(Ordering::Relaxed), children[i].load(Ordering::Acquire)));
+                }
+            },
+            N48 => {
+                let n = self.as48();
+                for c in 0..256usize {
+                    let i = n.index[c].load(Ordering::Relaxed);
+                    if i != 0 {
+                        res.push((c as u8, n.children[i as usize - 1].load(Ordering::Acquire)));
+                    }
+                }
+            },
+            _ => {
+                let n = self.as256();
+                for c in 0..256usize {
+                    let ch = n.children[c].load(Ordering::Acquire);
+                    if ch != 0 {
+                        res.push((c as u8, ch));
+                    }
+                }
+            },
+        }
+        res
+    }
+
+    fn is_full(&self) -> bool {
+        match self.kind {
+            N4 => self.count() == 4,
+            N16 => self.count() == 16,
+            N48 => self.count() == 48,
+            _ => false,
+        }
+    }
+
+    // the node is to shrink when count children are left, as in Art
+    fn need_shrink(&self, count: usize) -> bool {
+        match self.kind {
+            N4 => false,
+            N16 => count < 3,
+            N48 => count < 15,
+            _ => count < 46,
+        }
+    }
+
+    // caller makes sure the node is not full
+    unsafe fn add_child(&self, c: u8, ch: usize) {
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.sorted();
+                let n = self.count();
+                let mut i = n;
+                while i > 0 && keys[i - 1].load(Ordering::Relaxed) > c {
+                    keys[i].store(keys[i - 1].load(Ordering::Relaxed), Ordering::Relaxed);
+                    children[i].store(children[i - 1].load(Ordering::Relaxed), Ordering::Release);
+                    i -= 1;
+                }
+                keys[i].store(c, Ordering::Relaxed);
+                children[i].store(ch, Ordering::Release);
+            },
+            N48 => {
+                let n = self.as48();
+                let slot = n.children.iter().position(|s| s.load(Ordering::Relaxed) == 0).unwrap();
+                n.children[slot].store(ch, Ordering::Release);
+                n.index[c as usize].store(slot as u8 + 1, Ordering::Release);
+            },
+            _ => self.as256().children[c as usize].store(ch, Ordering::Release),
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe fn del_child(&self, c: u8) {
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.sorted();
+                let n = self.count();
+                let mut i = match (0..n).find(|&i| keys[i].load(Ordering::Relaxed) == c) {
+                    Some(i) => i,
+                    None => return,
+                };
+                while i + 1 < n {
+                    keys[i].store(keys[i + 1].load(Ordering::Relaxed), Ordering::Relaxed);
+                    children[i].store(children[i + 1].load(Ordering::Relaxed), Ordering::Release);
+                    i += 1;
+                }
+                children[n - 1].store(0, Ordering::Release);
+            },
+            N48 => {
+                let n = self.as48();
+                let i = n.index[c as usize].swap(0, Ordering::Release);
+                if i == 0 {
+                    return;
+                }
+                n.children[i as usize - 1].store(0, Ordering::Release);
+            },
+            _ => {
+                if self.as256().children[c as usize].swap(0, Ordering::Release) == 0 {
+                    return;
+                }
+            },
+        }
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    unsafe fn replace_child(&self, c: u8, ch: usize) {
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.sorted();
+                for i in 0..self.count() {
+                    if keys[i].load(Ordering::Relaxed) == c {
+                        children[i].store(ch, Ordering::Release);
+                    }
+                }
+            },
+            N48 => {
+                let n = self.as48();
+                let i = n.index[c as usize].load(Ordering::Relaxed);
+                n.children[i as usize - 1].store(ch, Ordering::Release);
+            },
+            _ => self.as256().children[c as usize].store(ch, Ordering::Release),
+        }
+    }
+
+    // a copy of this locked node as another type, to replace it
+    unsafe fn copy_as(&self, kind: u8) -> *mut Header<V> {
+        let n = new_node::<V>(kind, self.prefix());
+        (*n).leaf.store(self.leaf.load(Ordering::Relaxed), Ordering::Relaxed);
+        for (c, ch) in self.children() {
+            (*n).add_child(c, ch);
+        }
+        n
+    }
+}
+
+pub struct OlcArt<V> {
+    // a Node256 which is never replaced
+    root: *mut Header<V>,
+    len: AtomicUsize,
+    collector: Collector,
+}
+
+unsafe impl<V: Send + Sync> Send for OlcArt<V> {}
+unsafe impl<V: Send + Sync> Sync for OlcArt<V> {}
+
+impl<V: Clone> OlcArt<V> {
+
+    pub fn new() -> OlcArt<V> {
+        OlcArt {
+            root: new_node(N256, &[]),
+            len: AtomicUsize::new(0),
+            collector: Collector::new(),
+        }
+    }
+
+    // number of keys
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    // set the value of key, returns the value it replaces
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&self, key: &K, val: V) -> Option<V> {
+        let cs = key.as_ref();
+        let guard = self.collector.pin();
+        let leaf = Box::into_raw(Box::new(Leaf {
+            key: cs.to_vec(),
+            value: val,
+        }));
+        loop {
+            if let Some(old) = unsafe { self.try_insert(cs, leaf, &guard) } {
+                if old.is_none() {
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                }
+                return old;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    pub fn search<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<V> {
+        let cs = key.as_ref();
+        let _guard = self.collector.pin();
+        loop {
+            if let Some(res) = unsafe { self.try_search(cs) } {
+                return res;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    // remove key, returns its value
+    pub fn delete<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<V> {
+        let cs = key.as_ref();
+        let guard = self.collector.pin();
+        loop {
+            if let Some(old) = unsafe { self.try_delete(cs, &guard) } {
+                if old.is_some() {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                }
+                return old;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    // The try_ functions return None when they have to restart.
+
+    unsafe fn try_search(&self, key: &[u8]) -> Option<Option<V>> {
+        let mut node = self.root;
+        let mut v = (*node).read_lock()?;
+        let mut depth = 0usize;
+
+        loop {
+            let n = &*node;
+            let prefix = n.prefix();
+            if !key[depth..].starts_with(prefix) {
+                return if n.check(v) { Some(None) } else { None };
+            }
+            depth += prefix.len();
+
+            let leaf = if depth == key.len() {
+                n.leaf.load(Ordering::Acquire)
+            } else {
+                let child = n.find_child(key[depth]);
+                if !n.check(v) {
+                    return None;
+                }
+                if child == 0 {
+                    return Some(None);
+                }
+                if child & LEAF_TAG == 0 {
+                    let next = child as *mut Header<V>;
+                    let nv = (*next).read_lock()?;
+                    if !n.check(v) {
+                        return None;
+                    }
+                    node = next;
+                    v = nv;
+                    depth += 1;
+                    continue;
+                }
+                (child & !LEAF_TAG) as *mut Leaf<V>
+            };
+            if !n.check(v) {
+                return None;
+            }
+            if leaf.is_null() || (*leaf).key.as_slice() != key {
+                return Some(None);
+            }
+            return Some(Some((*leaf).value.clone()));
+        }
+    }
+
+    unsafe fn try_insert(&self, key: &[u8], leaf: *mut Leaf<V>, guard: &Guard) -> Option<Option<V>> {
+        let mut parent: *mut Header<V> = ptr::null_mut();
+        let mut pv = 0u64;
+        let mut pc = 0u8;
+        let mut node = self.root;
+        let mut v = (*node).read_lock()?;
+        let mut depth = 0usize;
+
+        loop {
+            let n = &*node;
+            let prefix = n.prefix();
+            let p = common_prefix(prefix, &key[depth..]);
+            if p < prefix.len() {
+                // key leaves the compressed path, a node4 takes the common
+                // part. The root has no prefix, so there is a parent.
+                let pn = &*parent;
+                if !pn.upgrade(pv) {
+                    return None;
+                }
+                if !n.upgrade(v) {
+                    pn.unlock();
+                    return None;
+                }
+                let split = new_node::<V>(N4, &prefix[..p]);
+                (*split).add_child(prefix[p], node as usize);
+                put_leaf(&*split, depth + p, leaf);
+                n.set_prefix(&prefix[p + 1..], guard);
+                pn.replace_child(pc, split as usize);
+                n.unlock();
+                pn.unlock();
+                return Some(None);
+            }
+            depth += prefix.len();
+
+            if depth == key.len() {
+                if !n.upgrade(v) {
+                    return None;
+                }
+                let old = n.leaf.swap(leaf, Ordering::AcqRel);
+                n.unlock();
+                if old.is_null() {
+                    return Some(None);
+                }
+                let val = (*old).value.clone();
+                guard.defer_drop(old);
+                return Some(Some(val));
+            }
+
+            let c = key[depth];
+            let child = n.find_child(c);
+            if !n.check(v) {
+                return None;
+            }
+
+            if child == 0 {
+                if !n.is_full() {
+                    if !n.upgrade(v) {
+                        return None;
+                    }
+                    n.add_child(c, leaf_word(leaf));
+                    n.unlock();
+                    return Some(None);
+                }
+                // a bigger copy replaces the node in its parent, the root
+                // is a Node256 and never full
+                let pn = &*parent;
+                if !pn.upgrade(pv) {
+                    return None;
+                }
+                if !n.upgrade(v) {
+                    pn.unlock();
+                    return None;
+                }
+                let big = n.copy_as(n.kind + 1);
+                (*big).add_child(c, leaf_word(leaf));
+                pn.replace_child(pc, big as usize);
+                n.unlock_obsolete();
+                retire_node(guard, node);
+                pn.unlock();
+                return Some(None);
+            }
+
+            if child & LEAF_TAG != 0 {
+                let old = (child & !LEAF_TAG) as *mut Leaf<V>;
+                if !n.upgrade(v) {
+                    return None;
+                }
+                if (*old).key.as_slice() == key {
+                    n.replace_child(c, leaf_word(leaf));
+                    n.unlock();
+                    let val = (*old).value.clone();
+                    guard.defer_drop(old);
+                    return Some(Some(val));
+                }
+                // lazy expansion: a node4 holds both leaves below their common part
+                let d = depth + 1;
+                let old_key = &(*old).key;
+                let p = common_prefix(&old_key[d..], &key[d..]);
+                let split = new_node::<V>(N4, &key[d..d + p]);
+                put_leaf(&*split, d + p, old);
+                put_leaf(&*split, d + p, leaf);
+                n.replace_child(c, split as usize);
+                n.unlock();
+                return Some(None);
+            }
+
+            parent = node;
+            pv = v;
+            pc = c;
+            node = child as *mut Header<V>;
+            v = (*node).read_lock()?;
+            if !(*parent).check(pv) {
+                return None;
+            }
+            depth += 1;
+        }
+    }
+
+    unsafe fn try_delete(&self, key: &[u8], guard: &Guard) -> Option<Option<V>> {
+        let mut parent: *mut Header<V> = ptr::null_mut();
+        let mut pv = 0u64;
+        let mut pc = 0u8;
+        let mut node = self.root;
+        let mut v = (*node).read_lock()?;
+        let mut depth = 0usize;
+
+        loop {
+            let n = &*node;
+            let prefix = n.prefix();
+            if !key[depth..].starts_with(prefix) {
+                return if n.check(v) { Some(None) } else { None };
+            }
+            depth += prefix.len();
+
+            // the leaf of key, at the header or below edge
+            let (leaf, edge) = if depth == key.len() {
+                (n.leaf.load(Ordering::Acquire), None)
+            } else {
+                let c = key[depth];
+                let child = n.find_child(c);
+                if !n.check(v) {
+                    return None;
+                }
+                if child == 0 {
+                    return Some(None);
+                }
+                if child & LEAF_TAG == 0 {
+                    parent = node;
+                    pv = v;
+                    pc = c;
+                    node = child as *mut Header<V>;
+                    v = (*node).read_lock()?;
+                    if !(*parent).check(pv) {
+                        return None;
+                    }
+                    depth += 1;
+                    continue;
+                }
+                ((child & !LEAF_TAG) as *mut Leaf<V>, Some(c))
+            };
+            if !n.check(v) {
+                return None;
+            }
+            if leaf.is_null() || (*leaf).key.as_slice() != key {
+                return Some(None);
+            }
+
+            // what is left in the node decides if the parent is locked too;
+            // it is read optimistically, the upgrade checks it; a concurrent
+            // delete can empty the node before that
+            let count = n.count().saturating_sub(edge.is_some() as usize);
+            let has_leaf = edge.is_some() && !n.leaf.load(Ordering::Relaxed).is_null();
+            let restructure = node != self.root
+                && (count + has_leaf as usize <= 1 || n.need_shrink(count));
+
+            if !restructure {
+                if !n.upgrade(v) {
+                    return None;
+                }
+                match edge {
+                    Some(c) => n.del_child(c),
+                    None => n.leaf.store(ptr::null_mut(), Ordering::Release),
+                }
+                n.unlock();
+                let val = (*leaf).value.clone();
+                guard.defer_drop(leaf);
+                return Some(Some(val));
+            }
+
+            let pn = &*parent;
+            if !pn.upgrade(pv) {
+                return None;
+            }
+            if !n.upgrade(v) {
+                pn.unlock();
+                return None;
+            }
+            match edge {
+                Some(c) => n.del_child(c),
+                None => n.leaf.store(ptr::null_mut(), Ordering::Release),
+            }
+
+            let rest = n.children();
+            let own = n.leaf.load(Ordering::Relaxed);
+            let replace = if rest.is_empty() && own.is_null() {
+                // nothing left
+                Some(0)
+            } else if rest.is_empty() {
+                Some(leaf_word(own))
+            } else if rest.len() == 1 && own.is_null() && rest[0].1 & LEAF_TAG != 0 {
+                Some(rest[0].1)
+            } else if n.need_shrink(rest.len()) {
+                Some(n.copy_as(n.kind - 1) as usize)
+            } else {
+                None
+            };
+
+            match replace {
+                Some(0) => {
+                    pn.del_child(pc);
+                    n.unlock_obsolete();
+                    retire_node(guard, node);
+                },
+                Some(ch) => {
+                    pn.replace_child(pc, ch);
+                    n.unlock_obsolete();
+                    retire_node(guard, node);
+                },
+                // a single inner child is kept
+                None => n.unlock(),
+            }
+            pn.unlock();
+            let val = (*leaf).value.clone();
+            guard.defer_drop(leaf);
+            return Some(Some(val));
+        }
+    }
+}
+
+impl<V> Drop for OlcArt<V> {
+    fn drop(&mut self) {
+        unsafe { free_tree::<V>(self.root as usize) };
+    }
+}
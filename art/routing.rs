@@ -0,0 +1,234 @@
+// IPv4 and IPv6 routing table on Art, with longest prefix match.
+//
+// Art branches on whole bytes, a prefix of n bits is split into its n / 8
+// whole bytes and n % 8 bits left over. The whole bytes, after a byte for the
+// address family, are the Art key. All prefixes with the same key, at most
+// 255 of them, sit in one list of partial-key routes: the bits left over and
+// the value. A lookup walks down the path of the address once, and at every
+// key on the way checks the partial bits of the next address byte.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::art::Art;
+
+// an address with the length of its prefix, host bits cleared
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    len: u8,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseCidrError {
+    InvalidAddr,
+    // not a number or longer than the address
+    InvalidLen,
+}
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseCidrError::InvalidAddr => write!(f, "invalid address"),
+            ParseCidrError::InvalidLen => write!(f, "invalid prefix length"),
+        }
+    }
+}
+
+fn addr_bytes(addr: &IpAddr) -> Vec<u8> {
+    match *addr {
+        IpAddr::V4(a) => a.octets().to_vec(),
+        IpAddr::V6(a) => a.octets().to_vec(),
+    }
+}
+
+fn family(addr: &IpAddr) -> u8 {
+    match *addr {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 6,
+    }
+}
+
+// mask of the first bits bits of a byte
+fn high_bits(bits: u8) -> u8 {
+    !(0xffu16 >> bits) as u8
+}
+
+impl Cidr {
+    // None when len is longer than the address
+    pub fn new(addr: IpAddr, len: u8) -> Option<Cidr> {
+        let mut bytes = addr_bytes(&addr);
+        if len as usize > bytes.len() * 8 {
+            return None;
+        }
+        let whole = len as usize / 8;
+        if whole < bytes.len() {
+            bytes[whole] &= high_bits(len % 8);
+            for b in bytes[whole + 1..].iter_mut() {
+                *b = 0;
+            }
+        }
+        let addr = match addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+            IpAddr::V6(_) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&bytes);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            },
+        };
+        Some(Cidr { addr, len })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    // the Art key of the whole bytes, the bits left over and their byte
+    fn split(&self) -> (Vec<u8>, u8, u8) {
+        let bytes = addr_bytes(&self.addr);
+        let whole = self.len as usize / 8;
+        let mut key = vec![family(&self.addr)];
+        key.extend_from_slice(&bytes[..whole]);
+        let bits = self.len % 8;
+        let rest = if bits > 0 { bytes[whole] } else { 0 };
+        (key, bits, rest)
+    }
+}
+
+// a.b.c.d/n or an IPv6 address with /n, without /n the prefix is the whole address
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Cidr, ParseCidrError> {
+        let (addr, len) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| ParseCidrError::InvalidAddr)?;
+        let len = match len {
+            Some(len) => len.parse().map_err(|_| ParseCidrError::InvalidLen)?,
+            None => addr_bytes(&addr).len() as u8 * 8,
+        };
+        Cidr::new(addr, len).ok_or(ParseCidrError::InvalidLen)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+struct Route<V> {
+    // bits of the prefix after the whole bytes of the key, 0 to 7
+    bits: u8,
+    // the byte holding them, the other bits cleared
+    rest: u8,
+    value: V,
+}
+
+pub struct RoutingTable<V> {
+    art: Art<Vec<Route<V>>>,
+    len: usize,
+}
+
+impl<V> RoutingTable<V> {
+    pub fn new() -> RoutingTable<V> {
+        RoutingTable {
+            art: Art::new(),
+            len: 0,
+        }
+    }
+
+    // number of prefixes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // set the value of a prefix, returns the value it replaces
+    pub fn insert(&mut self, cidr: Cidr, val: V) -> Option<V> {
+        let (key, bits, rest) = cidr.split();
+        let routes = self.art.get_or_insert_with(&key, Vec::new);
+        for r in routes.iter_mut() {
+            if r.bits == bits && r.rest == rest {
+                return Some(std::mem::replace(&mut r.value, val));
+            }
+        }
+        routes.push(Route { bits, rest, value: val });
+        self.len += 1;
+        None
+    }
+
+    pub fn get(&self, cidr: Cidr) -> Option<&V> {
+        let (key, bits, rest) = cidr.split();
+        let routes = self.art.search(&key)?;
+        routes.iter().find(|r| r.bits == bits && r.rest == rest).map(|r| &r.value)
+    }
+
+    pub fn remove(&mut self, cidr: Cidr) -> Option<V> {
+        let (key, bits, rest) = cidr.split();
+        let (val, empty) = {
+            let routes = self.art.search_mut(&key)?;
+            let i = routes.iter().position(|r| r.bits == bits && r.rest == rest)?;
+            let val = routes.swap_remove(i).value;
+            (val, routes.is_empty())
+        };
+        if empty {
+            self.art.delete(&key);
+        }
+        self.len -= 1;
+        Some(val)
+    }
+
+    // the longest prefix holding addr, without allocating
+    pub fn lookup(&self, addr: IpAddr) -> Option<(Cidr, &V)> {
+        // the family byte and the address bytes
+        let mut key = [0u8; 17];
+        key[0] = family(&addr);
+        let n = match addr {
+            IpAddr::V4(a) => { key[1..5].copy_from_slice(&a.octets()); 4 },
+            IpAddr::V6(a) => { key[1..].copy_from_slice(&a.octets()); 16 },
+        };
+        // keys come shortest first, the last match is the longest
+        let mut best = None;
+        self.art.for_each_prefix(&key[..n + 1], |k, routes| {
+            let whole = k.len() - 1;
+            let m = routes.iter().filter(|r| {
+                r.bits == 0 || (whole < n && key[whole + 1] & high_bits(r.bits) == r.rest)
+            }).max_by_key(|r| r.bits);
+            if let Some(r) = m {
+                best = Some((whole, r));
+            }
+        });
+        best.map(|(whole, r)| (Cidr::new(addr, (whole * 8) as u8 + r.bits).unwrap(), &r.value))
+    }
+
+    // every prefix holding addr, shortest first
+    pub fn covering(&self, addr: IpAddr) -> Vec<(Cidr, &V)> {
+        let bytes = addr_bytes(&addr);
+        let mut key = vec![family(&addr)];
+        key.extend_from_slice(&bytes);
+        let mut found = Vec::new();
+        self.art.for_each_prefix(&key, |k, routes| {
+            let whole = k.len() - 1;
+            let mut matches: Vec<&Route<V>> = routes.iter().filter(|r| {
+                r.bits == 0 || (whole < bytes.len() && bytes[whole] & high_bits(r.bits) == r.rest)
+            }).collect();
+            matches.sort_by_key(|r| r.bits);
+            for r in matches {
+                let len = (whole * 8) as u8 + r.bits;
+                found.push((Cidr::new(addr, len).unwrap(), &r.value));
+            }
+        });
+        found
+    }
+}
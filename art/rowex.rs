@@ -0,0 +1,799 @@
+// Concurrent ART with read-optimized write exclusion (ROWEX).
+//
+// Readers take no locks and never restart: every change a writer makes is a
+// single atomic store, and each state in between is one a reader can use.
+// Writers lock the nodes they change, the parent first when the node itself
+// is replaced, and restart when a node they locked was replaced meanwhile.
+//
+//  - Node4/Node16/Node48 fill their slots in order and never reuse one. A
+//    removed child leaves an empty slot behind, a node out of slots is
+//    replaced by a copy, of the same type if the children still fit.
+//  - A node that grows, shrinks or is compacted is copied, and the copy goes
+//    into the parent with one pointer store.
+//  - Leaves never change, a new value is a new leaf.
+//  - Every node knows its level, the position in the key of the byte which
+//    picks its child. When a key splits a compressed path, the new node goes
+//    into the parent before the prefix of the old node is cut, so a reader
+//    may see the old prefix with the new parent or the other way round. It
+//    only compares the bytes of the prefix which are also on its own path up
+//    to the level, and the leaf checks the whole key anyway.
+//
+// Unlinked nodes, leaves and prefixes go to the epoch collector. As in
+// OlcArt, a node left with a single inner child is not merged into its parent,
+// and the shrink thresholds are fixed to the ones of Thresholds::default(),
+// ArtConfig and its resize policies are not supported.
+
+use std::hint;
+use std::ptr;
+use std::thread;
+use std::sync::atomic::{AtomicPtr, AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use crate::epoch::{Collector, Guard};
+
+const OBSOLETE: u64 = 0b01;
+const LOCKED: u64 = 0b10;
+
+const N4: u8 = 0;
+const N16: u8 = 1;
+const N48: u8 = 2;
+const N256: u8 = 3;
+
+// A child word is 0 for none, a leaf pointer with the low bit set,
+// or a pointer to the header of an inner node.
+const LEAF_TAG: usize = 1;
+
+struct Leaf<V> {
+    key: Vec<u8>,
+    value: V,
+}
+
+// first field of every inner node, so a pointer to it is a node pointer
+#[repr(C)]
+struct Header<V> {
+    lock: AtomicU64,
+    kind: u8,
+    // bytes of the key above the child edge of this node
+    level: usize,
+    // children in the node
+    count: AtomicU16,
+    // slots filled so far, Node4/Node16/Node48
+    used: AtomicU16,
+    // compressed path, replaced as a whole and never null
+    prefix: AtomicPtr<Vec<u8>>,
+    // the key which ends at this node
+    leaf: AtomicPtr<Leaf<V>>,
+}
+
+#[repr(C)]
+struct Node4<V> {
+    hdr: Header<V>,
+    keys: [AtomicU8; 4],
+    children: [AtomicUsize; 4],
+}
+
+#[repr(C)]
+struct Node16<V> {
+    hdr: Header<V>,
+    keys: [AtomicU8; 16],
+    children: [AtomicUsize; 16],
+}
+
+#[repr(C)]
+struct Node48<V> {
+    hdr: Header<V>,
+    // slot of the child in children plus one, 0 if none
+    index: [AtomicU8; 256],
+    children: [AtomicUsize; 48],
+}
+
+#[repr(C)]
+struct Node256<V> {
+    hdr: Header<V>,
+    children: [AtomicUsize; 256],
+}
+
+fn leaf_word<V>(leaf: *mut Leaf<V>) -> usize {
+    leaf as usize | LEAF_TAG
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let mut i = 0usize;
+    while i < a.len() && i < b.len() && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+fn capacity(kind: u8) -> usize {
+    match kind {
+        N4 => 4,
+        N16 => 16,
+        N48 => 48,
+        _ => 256,
+    }
+}
+
+fn header<V>(kind: u8, level: usize, prefix: &[u8]) -> Header<V> {
+    Header {
+        lock: AtomicU64::new(0),
+        kind,
+        level,
+        count: AtomicU16::new(0),
+        used: AtomicU16::new(0),
+        prefix: AtomicPtr::new(Box::into_raw(Box::new(prefix.to_vec()))),
+        leaf: AtomicPtr::new(ptr::null_mut()),
+    }
+}
+
+fn new_node<V>(kind: u8, level: usize, prefix: &[u8]) -> *mut Header<V> {
+    match kind {
+        N4 => Box::into_raw(Box::new(Node4 {
+            hdr: header::<V>(kind, level, prefix),
+            keys: std::array::from_fn(|_| AtomicU8::new(0)),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+        N16 => Box::into_raw(Box::new(Node16 {
+            hdr: header::<V>(kind, level, prefix),
+            keys: std::array::from_fn(|_| AtomicU8::new(0)),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+        N48 => Box::into_raw(Box::new(Node48 {
+            hdr: header::<V>(kind, level, prefix),
+            index: std::array::from_fn(|_| AtomicU8::new(0)),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+        _ => Box::into_raw(Box::new(Node256 {
+            hdr: header::<V>(kind, level, prefix),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        })) as *mut Header<V>,
+    }
+}
+
+// free an inner node, not its children nor its leaf
+unsafe fn free_node<V>(n: *mut Header<V>) {
+    match (*n).kind {
+        N4 => drop(Box::from_raw(n as *mut Node4<V>)),
+        N16 => drop(Box::from_raw(n as *mut Node16<V>)),
+        N48 => drop(Box::from_raw(n as *mut Node48<V>)),
+        _ => drop(Box::from_raw(n as *mut Node256<V>)),
+    }
+}
+
+unsafe fn retire_node<V>(guard: &Guard, n: *mut Header<V>) {
+    match (*n).kind {
+        N4 => guard.defer_drop(n as *mut Node4<V>),
+        N16 => guard.defer_drop(n as *mut Node16<V>),
+        N48 => guard.defer_drop(n as *mut Node48<V>),
+        _ => guard.defer_drop(n as *mut Node256<V>),
+    }
+}
+
+// free a whole subtree, nobody else may use it
+unsafe fn free_tree<V>(ch: usize) {
+    if ch == 0 {
+        return;
+    }
+    if ch & LEAF_TAG != 0 {
+        drop(Box::from_raw((ch & !LEAF_TAG) as *mut Leaf<V>));
+        return;
+    }
+    let n = ch as *mut Header<V>;
+    for (_, c) in (*n).children() {
+        free_tree::<V>(c);
+    }
+    let leaf = (*n).leaf.load(Ordering::Relaxed);
+    if !leaf.is_null() {
+        drop(Box::from_raw(leaf));
+    }
+    free_node(n);
+}
+
+// hang a leaf on a node which is not published yet
+unsafe fn put_leaf<V>(n: &Header<V>, leaf: *mut Leaf<V>) {
+    let key = &(*leaf).key;
+    if n.level == key.len() {
+        n.leaf.store(leaf, Ordering::Release);
+    } else {
+        n.add_child(key[n.level], leaf_word(leaf));
+    }
+}
+
+// lock parent and then node, if parent still holds node below edge c
+unsafe fn lock_pair<V>(parent: &Header<V>, c: u8, node: &Header<V>) -> bool {
+    if !parent.lock() {
+        return false;
+    }
+    if parent.find_child(c) != node as *const Header<V> as usize || !node.lock() {
+        parent.unlock();
+        return false;
+    }
+    true
+}
+
+impl<V> Drop for Header<V> {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(*self.prefix.get_mut())) };
+    }
+}
+
+impl<V> Header<V> {
+    // node lock
+
+    // false if the node was replaced, the writer restarts then
+    fn lock(&self) -> bool {
+        loop {
+            let v = self.lock.load(Ordering::Relaxed);
+            if v & OBSOLETE != 0 {
+                return false;
+            }
+            if v & LOCKED == 0 && self.lock
+                .compare_exchange_weak(v, v | LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok() {
+                return true;
+            }
+            thread::yield_now();
+        }
+    }
+
+    fn unlock(&self) {
+        self.lock.fetch_and(!LOCKED, Ordering::Release);
+    }
+
+    // unlock a node which was replaced, writers waiting for it restart
+    fn unlock_obsolete(&self) {
+        self.lock.store(OBSOLETE, Ordering::Release);
+    }
+
+    // node access, writes need the lock
+
+    unsafe fn set_prefix(&self, prefix: &[u8], guard: &Guard) {
+        let p = Box::into_raw(Box::new(prefix.to_vec()));
+        let old = self.prefix.swap(p, Ordering::AcqRel);
+        guard.defer_drop(old);
+    }
+
+    fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed) as usize
+    }
+
+    fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire) as usize
+    }
+
+    unsafe fn as48(&self) -> &Node48<V> {
+        &*(self as *const Header<V> as *const Node48<V>)
+    }
+
+    unsafe fn as256(&self) -> &Node256<V> {
+        &*(self as *const Header<V> as *const Node256<V>)
+    }
+
+    // edges and children of Node4/Node16, the first used slots are filled
+    unsafe fn slots(&self) -> (&[AtomicU8], &[AtomicUsize]) {
+        let p = self as *const Header<V>;
+        match self.kind {
+            N4 => (&(*(p as *const Node4<V>)).keys, &(*(p as *const Node4<V>)).children),
+            _ => (&(*(p as *const Node16<V>)).keys, &(*(p as *const Node16<V>)).children),
+        }
+    }
+
+    // Node4/Node16 slot of the child at edge c
+    unsafe fn slot_of(&self, c: u8) -> Option<usize> {
+        let (keys, children) = self.slots();
+        let n = self.used().min(keys.len());
+        // an edge removed and added again has an empty slot before
+        (0..n).find(|&i| {
+            keys[i].load(Ordering::Relaxed) == c && children[i].load(Ordering::Relaxed) != 0
+        })
+    }
+
+    unsafe fn find_child(&self, c: u8) -> usize {
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.slots();
+                let n = self.used().min(keys.len());
+                for i in 0..n {
+                    if keys[i].load(Ordering::Relaxed) == c {
+                        let ch = children[i].load(Ordering::Acquire);
+                        if ch != 0 {
+                            return ch;
+                        }
+                    }
+                }
+                0
+            },
+            N48 => {
+                let n = self.as48();
+                match n.index[c as usize].load(Ordering::Acquire) {
+                    0 => 0,
+                    i => n.children[i as usize - 1].load(Ordering::Acquire),
+                }
+            },
+            _ => self.as256().children[c as usize].load(Ordering::Acquire),
+        }
+    }
+
+    // edges and children, Node4/Node16 in slot order
+    unsafe fn children(&self) -> Vec<(u8, usize)> {
+        let mut res = Vec::new();
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.slots();
+                for i in 0..self.used() {
+                    let ch = children[i].load(Ordering::Acquire);
+                    if ch != 0 {
+                        res.push((keys[i].load(Ordering::Relaxed), ch));
+                    }
+                }
+            },
+            N48 => {
+                let n = self.as48();
+                for c in 0..256usize {
+                    let i = n.index[c].load(Ordering::Acquire);
+                    if i != 0 {
+                        res.push((c as u8, n.children[i as usize - 1].load(Ordering::Acquire)));
+                    }
+                }
+            },
+            _ => {
+                let n = self.as256();
+                for c in 0..256usize {
+                    let ch = n.children[c].load(Ordering::Acquire);
+                    if ch != 0 {
+                        res.push((c as u8, ch));
+                    }
+                }
+            },
+        }
+        res
+    }
+
+    // no free slot left
+    fn is_full(&self) -> bool {
+        match self.kind {
+            N256 => false,
+            kind => self.used() == capacity(kind),
+        }
+    }
+
+    // the node is to shrink when count children are left, as in Art
+    fn need_shrink(&self, count: usize) -> bool {
+        match self.kind {
+            N4 => false,
+            N16 => count < 3,
+            N48 => count < 15,
+            _ => count < 46,
+        }
+    }
+
+    // caller makes sure the node is not full; the child is in place before
+    // readers can find the slot
+    unsafe fn add_child(&self, c: u8, ch: usize) {
+        match self.kind {
+            N4 | N16 => {
+                let (keys, children) = self.slots();
+                let i = self.used();
+                keys[i].store(c, Ordering::Relaxed);
+                children[i].store(ch, Ordering::Release);
+                self.used.store(i as u16 + 1, Ordering::Release);
+            },
+            N48 => {
+                let n = self.as48();
+                let i = self.used();
+                n.children[i].store(ch, Ordering::Release);
+                n.index[c as usize].store(i as u8 + 1, Ordering::Release);
+                self.used.store(i as u16 + 1, Ordering::Release);
+            },
+            _ => self.as256().children[c as usize].store(ch, Ordering::Release),
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe fn del_child(&self, c: u8) {
+        match self.kind {
+            N4 | N16 => {
+                let i = match self.slot_of(c) {
+                    Some(i) => i,
+                    None => return,
+                };
+                self.slots().1[i].store(0, Ordering::Release);
+            },
+            N48 => {
+                let n = self.as48();
+                let i = n.index[c as usize].swap(0, Ordering::Release);
+                if i == 0 {
+                    return;
+                }
+                n.children[i as usize - 1].store(0, Ordering::Release);
+            },
+            _ => {
+                if self.as256().children[c as usize].swap(0, Ordering::Release) == 0 {
+                    return;
+                }
+            },
+        }
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    unsafe fn replace_child(&self, c: u8, ch: usize) {
+        match self.kind {
+            N4 | N16 => {
+                if let Some(i) = self.slot_of(c) {
+                    self.slots().1[i].store(ch, Ordering::Release);
+                }
+            },
+            N48 => {
+                let n = self.as48();
+                let i = n.index[c as usize].load(Ordering::Relaxed);
+                n.children[i as usize - 1].store(ch, Ordering::Release);
+            },
+            _ => self.as256().children[c as usize].store(ch, Ordering::Release),
+        }
+    }
+
+    // removing the leaf at edge, or the own leaf for None, leaves too
+    // little for a node of this type
+    fn restructure(&self, edge: Option<u8>) -> bool {
+        // read before the lock too, a concurrent delete may have taken the
+        // leaf already
+        let count = self.count().saturating_sub(edge.is_some() as usize);
+        let has_leaf = edge.is_some() && !self.leaf.load(Ordering::Relaxed).is_null();
+        count + has_leaf as usize <= 1 || self.need_shrink(count)
+    }
+
+    // a copy of this locked node as another type, to replace it
+    unsafe fn copy_as(&self, kind: u8) -> *mut Header<V> {
+        let n = new_node::<V>(kind, self.level, &*self.prefix.load(Ordering::Relaxed));
+        (*n).leaf.store(self.leaf.load(Ordering::Relaxed), Ordering::Relaxed);
+        for (c, ch) in self.children() {
+            (*n).add_child(c, ch);
+        }
+        n
+    }
+}
+
+pub struct RowexArt<V> {
+    // a Node256 which is never replaced
+    root: *mut Header<V>,
+    len: AtomicUsize,
+    collector: Collector,
+}
+
+unsafe impl<V: Send + Sync> Send for RowexArt<V> {}
+unsafe impl<V: Send + Sync> Sync for RowexArt<V> {}
+
+impl<V: Clone> RowexArt<V> {
+
+    pub fn new() -> RowexArt<V> {
+        RowexArt {
+            root: new_node(N256, 0, &[]),
+            len: AtomicUsize::new(0),
+            collector: Collector::new(),
+        }
+    }
+
+    // number of keys
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    // set the value of key, returns the value it replaces
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&self, key: &K, val: V) -> Option<V> {
+        let cs = key.as_ref();
+        let guard = self.collector.pin();
+        let leaf = Box::into_raw(Box::new(Leaf {
+            key: cs.to_vec(),
+            value: val,
+        }));
+        loop {
+            if let Some(old) = unsafe { self.try_insert(cs, leaf, &guard) } {
+                if old.is_none() {
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                }
+                return old;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    // never waits for writers
+    pub fn search<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<V> {
+        let cs = key.as_ref();
+        let _guard = self.collector.pin();
+        let mut node = self.root;
+        let mut depth = 0usize;
+
+        unsafe {
+            loop {
+                let n = &*node;
+                let level = n.level;
+                if cs.len() < level {
+                    return None;
+                }
+                // the prefix may be cut already or not yet, only the bytes
+                // next to the level are sure to be on this path
+                let prefix = &*n.prefix.load(Ordering::Acquire);
+                let k = prefix.len().min(level - depth);
+                if cs[level - k..level] != prefix[prefix.len() - k..] {
+                    return None;
+                }
+
+                let leaf = if cs.len() == level {
+                    n.leaf.load(Ordering::Acquire)
+                } else {
+                    let child = n.find_child(cs[level]);
+                    if child == 0 {
+                        return None;
+                    }
+                    if child & LEAF_TAG == 0 {
+                        node = child as *mut Header<V>;
+                        depth = level + 1;
+                        continue;
+                    }
+                    (child & !LEAF_TAG) as *mut Leaf<V>
+                };
+                if leaf.is_null() {
+                    return None;
+                }
+                let leaf = &*leaf;
+                if leaf.key.as_slice() != cs {
+                    return None;
+                }
+                return Some(leaf.value.clone());
+            }
+        }
+    }
+
+    // remove key, returns its value
+    pub fn delete<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<V> {
+        let cs = key.as_ref();
+        let guard = self.collector.pin();
+        loop {
+            if let Some(old) = unsafe { self.try_delete(cs, &guard) } {
+                if old.is_some() {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                }
+                return old;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    // The try_ functions return None when the writer has to restart. They
+    // only go on with a prefix which fits the level, so the whole path is
+    // checked, and what they saw unlocked is checked again under the lock.
+
+    unsafe fn try_insert(&self, key: &[u8], leaf: *mut Leaf<V>, guard: &Guard) -> Option<Option<V>> {
+        let mut parent: *mut Header<V> = ptr::null_mut();
+        let mut pc = 0u8;
+        let mut node = self.root;
+        let mut depth = 0usize;
+
+        loop {
+            let n = &*node;
+            let level = n.level;
+            let pp = n.prefix.load(Ordering::Acquire);
+            let prefix = &*pp;
+            if prefix.len() != level - depth {
+                // a split of this path is half done
+                return None;
+            }
+            let p = common_prefix(prefix, &key[depth..]);
+            if p < prefix.len() {
+                // key leaves the compressed path, a node4 takes the common
+                // part. The root has no prefix, so there is a parent.
+                let pn = &*parent;
+                if !lock_pair(pn, pc, n) {
+                    return None;
+                }
+                if n.prefix.load(Ordering::Relaxed) != pp {
+                    n.unlock();
+                    pn.unlock();
+                    return None;
+                }
+                let split = new_node::<V>(N4, depth + p, &prefix[..p]);
+                (*split).add_child(prefix[p], node as usize);
+                put_leaf(&*split, leaf);
+                pn.replace_child(pc, split as usize);
+                n.set_prefix(&prefix[p + 1..], guard);
+                n.unlock();
+                pn.unlock();
+                return Some(None);
+            }
+
+            if key.len() == level {
+                if !n.lock() {
+                    return None;
+                }
+                let old = n.leaf.swap(leaf, Ordering::AcqRel);
+                n.unlock();
+                if old.is_null() {
+                    return Some(None);
+                }
+                let val = (*old).value.clone();
+                guard.defer_drop(old);
+                return Some(Some(val));
+            }
+
+            let c = key[level];
+            let child = n.find_child(c);
+
+            if child == 0 {
+                if !n.is_full() {
+                    if !n.lock() {
+                        return None;
+                    }
+                    if n.is_full() || n.find_child(c) != 0 {
+                        n.unlock();
+                        return None;
+                    }
+                    n.add_child(c, leaf_word(leaf));
+                    n.unlock();
+                    return Some(None);
+                }
+                // out of slots: a copy replaces the node in its parent, of the
+                // next type if the children fill this one. The root is a
+                // Node256, never full.
+                let pn = &*parent;
+                if !lock_pair(pn, pc, n) {
+                    return None;
+                }
+                if n.find_child(c) != 0 {
+                    n.unlock();
+                    pn.unlock();
+                    return None;
+                }
+                let kind = if n.count() < capacity(n.kind) { n.kind } else { n.kind + 1 };
+                let big = n.copy_as(kind);
+                (*big).add_child(c, leaf_word(leaf));
+                pn.replace_child(pc, big as usize);
+                n.unlock_obsolete();
+                retire_node(guard, node);
+                pn.unlock();
+                return Some(None);
+            }
+
+            if child & LEAF_TAG != 0 {
+                if !n.lock() {
+                    return None;
+                }
+                if n.find_child(c) != child {
+                    n.unlock();
+                    return None;
+                }
+                let old = (child & !LEAF_TAG) as *mut Leaf<V>;
+                let old_key = &(*old).key;
+                if old_key.as_slice() == key {
+                    n.replace_child(c, leaf_word(leaf));
+                    n.unlock();
+                    let val = (*old).value.clone();
+                    guard.defer_drop(old);
+                    return Some(Some(val));
+                }
+                // lazy expansion: a node4 holds both leaves below their common part
+                let d = level + 1;
+                let p = common_prefix(&old_key[d..], &key[d..]);
+                let split = new_node::<V>(N4, d + p, &key[d..d + p]);
+                put_leaf(&*split, old);
+                put_leaf(&*split, leaf);
+                n.replace_child(c, split as usize);
+                n.unlock();
+                return Some(None);
+            }
+
+            parent = node;
+            pc = c;
+            node = child as *mut Header<V>;
+            depth = level + 1;
+        }
+    }
+
+    unsafe fn try_delete(&self, key: &[u8], guard: &Guard) -> Option<Option<V>> {
+        let mut parent: *mut Header<V> = ptr::null_mut();
+        let mut pc = 0u8;
+        let mut node = self.root;
+        let mut depth = 0usize;
+
+        loop {
+            let n = &*node;
+            let level = n.level;
+            let prefix = &*n.prefix.load(Ordering::Acquire);
+            if prefix.len() != level - depth {
+                return None;
+            }
+            if !key[depth..].starts_with(prefix) {
+                return Some(None);
+            }
+
+            // the leaf of key, at the header or below edge
+            let (leaf, edge) = if key.len() == level {
+                (n.leaf.load(Ordering::Acquire), None)
+            } else {
+                let c = key[level];
+                let child = n.find_child(c);
+                if child == 0 {
+                    return Some(None);
+                }
+                if child & LEAF_TAG == 0 {
+                    parent = node;
+                    pc = c;
+                    node = child as *mut Header<V>;
+                    depth = level + 1;
+                    continue;
+                }
+                ((child & !LEAF_TAG) as *mut Leaf<V>, Some(c))
+            };
+            if leaf.is_null() || (*leaf).key.as_slice() != key {
+                return Some(None);
+            }
+
+            // what is left in the node decides if the parent is locked too
+            let restructure = node != self.root && n.restructure(edge);
+            let locked = if restructure { lock_pair(&*parent, pc, n) } else { n.lock() };
+            if !locked {
+                return None;
+            }
+            let still = match edge {
+                Some(c) => n.find_child(c) == leaf_word(leaf),
+                None => n.leaf.load(Ordering::Relaxed) == leaf,
+            };
+            // the node changed before the lock
+            if !still || restructure != (node != self.root && n.restructure(edge)) {
+                n.unlock();
+                if restructure {
+                    (*parent).unlock();
+                }
+                return None;
+            }
+            match edge {
+                Some(c) => n.del_child(c),
+                None => n.leaf.store(ptr::null_mut(), Ordering::Release),
+            }
+
+            if restructure {
+                let pn = &*parent;
+                let rest = n.children();
+                let own = n.leaf.load(Ordering::Relaxed);
+                let replace = if rest.is_empty() && own.is_null() {
+                    // nothing left
+                    Some(0)
+                } else if rest.is_empty() {
+                    Some(leaf_word(own))
+                } else if rest.len() == 1 && own.is_null() && rest[0].1 & LEAF_TAG != 0 {
+                    Some(rest[0].1)
+                } else if n.need_shrink(rest.len()) {
+                    Some(n.copy_as(n.kind - 1) as usize)
+                } else {
+                    None
+                };
+
+                match replace {
+                    Some(0) => {
+                        pn.del_child(pc);
+                        n.unlock_obsolete();
+                        retire_node(guard, node);
+                    },
+                    Some(ch) => {
+                        pn.replace_child(pc, ch);
+                        n.unlock_obsolete();
+                        retire_node(guard, node);
+                    },
+                    // a single inner child is kept
+                    None => n.unlock(),
+                }
+                pn.unlock();
+            } else {
+                n.unlock();
+            }
+            let val = (*leaf).value.clone();
+            guard.defer_drop(leaf);
+            return Some(Some(val));
+        }
+    }
+}
+
+impl<V> Drop for RowexArt<V> {
+    fn drop(&mut self) {
+        unsafe { free_tree::<V>(self.root as usize) };
+    }
+}
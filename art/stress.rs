@@ -0,0 +1,305 @@
+#[allow(dead_code)]
+mod epoch;
+#[allow(dead_code)]
+mod olc;
+#[allow(dead_code)]
+mod rowex;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use olc::OlcArt;
+use rowex::RowexArt;
+
+// Multithreaded checks of the concurrent trees against BTreeMap models.
+// Build with optimizations: rustc -O stress.rs
+
+// counts live heap bytes, retired nodes must all be freed by the collector
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const THREADS: usize = 8;
+const OPS: usize = 200_000;
+
+// the operations both concurrent trees have
+trait ConcurrentArt<V>: Send + Sync + 'static {
+    fn new() -> Self;
+    fn insert(&self, key: &[u8], val: V) -> Option<V>;
+    fn search(&self, key: &[u8]) -> Option<V>;
+    fn delete(&self, key: &[u8]) -> Option<V>;
+    fn len(&self) -> usize;
+}
+
+macro_rules! concurrent_art {
+    ($($t:ident),*) => {$(
+        impl<V: Clone + Send + Sync + 'static> ConcurrentArt<V> for $t<V> {
+            fn new() -> Self {
+                $t::new()
+            }
+
+            fn insert(&self, key: &[u8], val: V) -> Option<V> {
+                $t::insert(self, key, val)
+            }
+
+            fn search(&self, key: &[u8]) -> Option<V> {
+                $t::search(self, key)
+            }
+
+            fn delete(&self, key: &[u8]) -> Option<V> {
+                $t::delete(self, key)
+            }
+
+            fn len(&self) -> usize {
+                $t::len(self)
+            }
+        }
+    )*}
+}
+
+concurrent_art!(OlcArt, RowexArt);
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Short keys over a small or a full alphabet, so nodes of every type fill
+// up and empty again. The second byte is the thread, every thread has keys
+// of its own but they share the nodes above.
+fn gen_key(rng: &mut Rng, t: usize) -> Vec<u8> {
+    let wide = rng.next().is_multiple_of(2);
+    let len = (rng.next() % 4) as usize;
+    let mut key = vec![(rng.next() % 3) as u8, t as u8];
+    for _ in 0..len {
+        let b = if wide { rng.next() % 256 } else { rng.next() % 5 };
+        key.push(b as u8);
+    }
+    key
+}
+
+// threads with keys of their own, every result must be the one of the model
+fn disjoint_keys<T: ConcurrentArt<usize>>() {
+    let tree = Arc::new(T::new());
+    let mut handles = Vec::new();
+    for t in 0..THREADS {
+        let tree = tree.clone();
+        handles.push(thread::spawn(move || {
+            let mut rng = Rng(0x9e3779b97f4a7c15 ^ t as u64);
+            let mut model = BTreeMap::new();
+            for i in 0..OPS {
+                let key = gen_key(&mut rng, t);
+                match rng.next() % 3 {
+                    0 => assert_eq!(tree.insert(&key, i), model.insert(key, i)),
+                    1 => assert_eq!(tree.delete(&key), model.remove(&key)),
+                    _ => assert_eq!(tree.search(&key), model.get(&key).cloned()),
+                }
+            }
+            model
+        }));
+    }
+
+    let mut all = BTreeMap::new();
+    for h in handles {
+        all.extend(h.join().unwrap());
+    }
+    for (k, v) in all.iter() {
+        assert_eq!(tree.search(k), Some(*v));
+    }
+    assert_eq!(tree.len(), all.len());
+    println!("disjoint keys: {} keys left", all.len());
+}
+
+// Threads fight over the same few keys while readers look up keys nobody
+// deletes. A value names the key it was written for.
+fn shared_keys<T: ConcurrentArt<Vec<u8>>>() {
+    let tree = Arc::new(T::new());
+    let mut stable = Vec::new();
+    for i in 0..2000u32 {
+        let key = format!("stable{}", i * 7919 % 2000).into_bytes();
+        tree.insert(&key, key.clone());
+        stable.push(key);
+    }
+    let stable = Arc::new(stable);
+    let mut hot: Vec<Vec<u8>> = (0..300u32).map(|i| {
+        let mut k = b"stable".to_vec();
+        k.push((i % 7) as u8);
+        k.extend_from_slice(&i.to_be_bytes()[2..]);
+        k.truncate(7 + (i % 3) as usize);
+        k
+    }).collect();
+    hot.sort();
+    hot.dedup();
+    let hot = Arc::new(hot);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut readers = Vec::new();
+    for _ in 0..2 {
+        let (tree, stable, done) = (tree.clone(), stable.clone(), done.clone());
+        readers.push(thread::spawn(move || {
+            let mut rounds = 0usize;
+            while !done.load(Ordering::Relaxed) {
+                for k in stable.iter() {
+                    assert_eq!(tree.search(k).as_ref(), Some(k));
+                }
+                rounds += 1;
+            }
+            rounds
+        }));
+    }
+
+    let mut writers = Vec::new();
+    for t in 0..THREADS {
+        let (tree, hot) = (tree.clone(), hot.clone());
+        writers.push(thread::spawn(move || {
+            let mut rng = Rng(0x2545f4914f6cdd1d ^ t as u64);
+            for _ in 0..OPS {
+                let key = &hot[(rng.next() % hot.len() as u64) as usize];
+                let res = match rng.next() % 3 {
+                    0 => tree.insert(key, key.clone()),
+                    1 => tree.delete(key),
+                    _ => tree.search(key),
+                };
+                if let Some(v) = res {
+                    assert_eq!(&v, key);
+                }
+            }
+        }));
+    }
+    for h in writers {
+        h.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    let rounds: usize = readers.into_iter().map(|h| h.join().unwrap()).sum();
+
+    let mut left = 0usize;
+    for k in hot.iter() {
+        if let Some(v) = tree.search(k) {
+            assert_eq!(&v, k);
+            left += 1;
+        }
+    }
+    for k in stable.iter() {
+        assert_eq!(tree.search(k).as_ref(), Some(k));
+    }
+    assert_eq!(tree.len(), stable.len() + left);
+    println!("shared keys: {} hot keys left, {} reader rounds", left, rounds);
+}
+
+// All threads insert and delete the keys of one small node, it is
+// emptied, replaced by its last leaf and made again all the time. A key
+// holds its own last byte as value, the tree must agree with itself at the end.
+fn small_node<T: ConcurrentArt<usize>>() {
+    let tree = Arc::new(T::new());
+    // the node is not the root, its parent may be locked too
+    tree.insert(b"m", 0);
+    // two children and a leaf of its own
+    let keys: Arc<Vec<Vec<u8>>> = Arc::new(vec![b"n".to_vec(), b"n\x00".to_vec(), b"n\x01".to_vec()]);
+    let mut handles = Vec::new();
+    for t in 0..THREADS {
+        let (tree, keys) = (tree.clone(), keys.clone());
+        handles.push(thread::spawn(move || {
+            let mut rng = Rng(0x9e3779b97f4a7c15 ^ t as u64);
+            for _ in 0..OPS {
+                let key = &keys[(rng.next() % keys.len() as u64) as usize];
+                let val = *key.last().unwrap() as usize;
+                // mostly deletes, so the count is often at 0 or 1
+                let res = match rng.next() % 3 {
+                    0 => tree.insert(key, val),
+                    _ => tree.delete(key),
+                };
+                if let Some(v) = res {
+                    assert_eq!(v, val);
+                }
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    let mut left = 0usize;
+    for k in keys.iter() {
+        if let Some(v) = tree.search(k) {
+            assert_eq!(v, *k.last().unwrap() as usize);
+            left += 1;
+        }
+    }
+    assert_eq!(tree.search(b"m"), Some(0));
+    assert_eq!(tree.len(), left + 1);
+    println!("small node: {} keys left", left);
+}
+
+// 99% lookups and 1% updates over a filled tree, time per operation
+fn read_mostly<T: ConcurrentArt<usize>>(name: &str) {
+    let tree = Arc::new(T::new());
+    let keys: Arc<Vec<Vec<u8>>> = Arc::new((0..100_000u32).map(|i| {
+        format!("user:{:08}", i.wrapping_mul(2654435761) % 100_000_000).into_bytes()
+    }).collect());
+    for (i, k) in keys.iter().enumerate() {
+        tree.insert(k, i);
+    }
+
+    let start = Instant::now();
+    let mut handles = Vec::new();
+    for t in 0..THREADS {
+        let (tree, keys) = (tree.clone(), keys.clone());
+        handles.push(thread::spawn(move || {
+            let mut rng = Rng(0x853c49e6748fea9b ^ t as u64);
+            for _ in 0..OPS {
+                let i = (rng.next() % keys.len() as u64) as usize;
+                if rng.next().is_multiple_of(100) {
+                    tree.insert(&keys[i], i);
+                } else {
+                    assert_eq!(tree.search(&keys[i]), Some(i));
+                }
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    let ns = start.elapsed().as_nanos() as f64 / (THREADS * OPS) as f64;
+    println!("{:<24} {:>8.1} ns/op", name, ns);
+}
+
+fn main() {
+    println!("{} threads, {} operations each", THREADS, OPS);
+    let before = LIVE.load(Ordering::Relaxed);
+    println!("optimistic lock coupling");
+    disjoint_keys::<OlcArt<usize>>();
+    shared_keys::<OlcArt<Vec<u8>>>();
+    small_node::<OlcArt<usize>>();
+    println!("rowex");
+    disjoint_keys::<RowexArt<usize>>();
+    shared_keys::<RowexArt<Vec<u8>>>();
+    small_node::<RowexArt<usize>>();
+    read_mostly::<OlcArt<usize>>("olc 99% lookups");
+    read_mostly::<RowexArt<usize>>("rowex 99% lookups");
+    let after = LIVE.load(Ordering::Relaxed);
+    println!("heap not reclaimed: {} bytes", after as isize - before as isize);
+}
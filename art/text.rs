@@ -0,0 +1,442 @@
+// Inverted full-text index on Art.
+//
+// Documents are split into lowercase alphanumeric terms. Each term is an Art
+// key whose value is its posting list: for every document holding the term,
+// by increasing id, the id and the positions of the term in the document.
+// Ids and positions are stored as varint deltas from the previous one. A
+// prefix query walks the terms under the prefix with prefix_iter.
+//
+// Query text: terms are ANDed, OR binds looser than AND, NOT excludes,
+// "a phrase" matches consecutive terms, term* matches terms by prefix and
+// parentheses group.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::art::Art;
+
+pub type DocId = u32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseQueryError {
+    Empty,
+    UnclosedQuote,
+    UnclosedParen,
+    // token which cannot start or continue a query
+    Unexpected(String),
+}
+
+impl fmt::Display for ParseQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseQueryError::Empty => write!(f, "empty query"),
+            ParseQueryError::UnclosedQuote => write!(f, "unclosed quote in query"),
+            ParseQueryError::UnclosedParen => write!(f, "unclosed parenthesis in query"),
+            ParseQueryError::Unexpected(ref t) => write!(f, "unexpected {} in query", t),
+        }
+    }
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut n: u32) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn get_varint(buf: &[u8], at: &mut usize) -> u32 {
+    let mut n = 0u32;
+    let mut shift = 0;
+    loop {
+        let b = buf[*at];
+        *at += 1;
+        n |= ((b & 0x7f) as u32) << shift;
+        if b < 0x80 {
+            return n;
+        }
+        shift += 7;
+    }
+}
+
+// the documents and positions of one term
+struct Postings {
+    bytes: Vec<u8>,
+    // id of the last document, new ids above it are appended
+    last: DocId,
+    docs: usize,
+}
+
+impl Postings {
+    fn new() -> Postings {
+        Postings {
+            bytes: Vec::new(),
+            last: 0,
+            docs: 0,
+        }
+    }
+
+    fn add(&mut self, doc: DocId, positions: &[u32]) {
+        if self.docs > 0 && doc <= self.last {
+            // out of order, decode and encode the whole list again
+            let mut all = self.decode();
+            let i = all.iter().position(|&(d, _)| d > doc).unwrap_or(all.len());
+            all.insert(i, (doc, positions.to_vec()));
+            *self = Postings::new();
+            for (d, pos) in all {
+                self.add(d, &pos);
+            }
+            return;
+        }
+        put_varint(&mut self.bytes, doc - self.last);
+        put_varint(&mut self.bytes, positions.len() as u32);
+        let mut prev = 0;
+        for &p in positions {
+            put_varint(&mut self.bytes, p - prev);
+            prev = p;
+        }
+        self.last = doc;
+        self.docs += 1;
+    }
+
+    fn decode(&self) -> Vec<(DocId, Vec<u32>)> {
+        let mut all = Vec::with_capacity(self.docs);
+        let mut at = 0;
+        let mut doc = 0;
+        while at < self.bytes.len() {
+            doc += get_varint(&self.bytes, &mut at);
+            let n = get_varint(&self.bytes, &mut at);
+            let mut pos = Vec::with_capacity(n as usize);
+            let mut p = 0;
+            for _ in 0..n {
+                p += get_varint(&self.bytes, &mut at);
+                pos.push(p);
+            }
+            all.push((doc, pos));
+        }
+        all
+    }
+
+    fn doc_ids(&self) -> Vec<DocId> {
+        self.decode().into_iter().map(|(d, _)| d).collect()
+    }
+}
+
+// set operations on sorted ids
+fn intersect(a: &[DocId], b: &[DocId]) -> Vec<DocId> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            i += 1;
+        } else if a[i] > b[j] {
+            j += 1;
+        } else {
+            out.push(a[i]);
+            i += 1;
+            j += 1;
+        }
+    }
+    out
+}
+
+fn union(a: &[DocId], b: &[DocId]) -> Vec<DocId> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    while i < a.len() || j < b.len() {
+        if j == b.len() || (i < a.len() && a[i] < b[j]) {
+            out.push(a[i]);
+            i += 1;
+        } else {
+            if i < a.len() && a[i] == b[j] {
+                i += 1;
+            }
+            out.push(b[j]);
+            j += 1;
+        }
+    }
+    out
+}
+
+fn difference(a: &[DocId], b: &[DocId]) -> Vec<DocId> {
+    let mut j = 0;
+    let mut out = Vec::new();
+    for &d in a {
+        while j < b.len() && b[j] < d {
+            j += 1;
+        }
+        if j == b.len() || b[j] != d {
+            out.push(d);
+        }
+    }
+    out
+}
+
+pub struct TextIndex {
+    terms: Art<Postings>,
+    // every document id, sorted, for NOT
+    docs: Vec<DocId>,
+}
+
+impl TextIndex {
+    pub fn new() -> TextIndex {
+        TextIndex {
+            terms: Art::new(),
+            docs: Vec::new(),
+        }
+    }
+
+    // number of documents
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    pub fn term_count(&self) -> usize {
+        self.terms.len()
+    }
+
+    // false when doc is already indexed
+    pub fn add(&mut self, doc: DocId, text: &str) -> bool {
+        let i = match self.docs.binary_search(&doc) {
+            Ok(_) => return false,
+            Err(i) => i,
+        };
+        self.docs.insert(i, doc);
+        let mut terms: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for (pos, t) in tokenize(text).into_iter().enumerate() {
+            terms.entry(t).or_default().push(pos as u32);
+        }
+        for (t, positions) in terms {
+            self.terms.get_or_insert_with(&t, Postings::new).add(doc, &positions);
+        }
+        true
+    }
+
+    // sorted ids of the documents matching the query
+    pub fn search(&self, q: &Query) -> Vec<DocId> {
+        match *q {
+            Query::Term(ref t) => match self.terms.search(t) {
+                Some(p) => p.doc_ids(),
+                None => Vec::new(),
+            },
+            Query::Prefix(ref t) => {
+                self.terms.prefix_iter(t).fold(Vec::new(), |docs, (_, p)| union(&docs, &p.doc_ids()))
+            },
+            Query::Phrase(ref ts) => self.phrase(ts),
+            Query::And(ref qs) => {
+                // NOT inside an AND only filters the others
+                let (nots, rest): (Vec<&Query>, Vec<&Query>) = qs.iter().partition(|q| matches!(**q, Query::Not(_)));
+                let mut docs = match rest.split_first() {
+                    Some((first, others)) => others.iter().fold(self.search(first), |docs, q| {
+                        if docs.is_empty() { docs } else { intersect(&docs, &self.search(q)) }
+                    }),
+                    None => self.docs.clone(),
+                };
+                for q in nots {
+                    if let Query::Not(ref q) = *q {
+                        docs = difference(&docs, &self.search(q));
+                    }
+                }
+                docs
+            },
+            Query::Or(ref qs) => qs.iter().fold(Vec::new(), |docs, q| union(&docs, &self.search(q))),
+            Query::Not(ref q) => difference(&self.docs, &self.search(q)),
+        }
+    }
+
+    // parse and search, see the top of the file for the syntax
+    pub fn query(&self, text: &str) -> Result<Vec<DocId>, ParseQueryError> {
+        let q: Query = text.parse()?;
+        Ok(self.search(&q))
+    }
+
+    fn phrase(&self, terms: &[String]) -> Vec<DocId> {
+        let mut lists = Vec::with_capacity(terms.len());
+        for t in terms {
+            match self.terms.search(t) {
+                Some(p) => lists.push(p.decode()),
+                None => return Vec::new(),
+            }
+        }
+        if lists.is_empty() {
+            return Vec::new();
+        }
+        let mut docs = lists[0].iter().map(|&(d, _)| d).collect::<Vec<_>>();
+        for l in lists[1..].iter() {
+            docs = intersect(&docs, &l.iter().map(|&(d, _)| d).collect::<Vec<_>>());
+        }
+        docs.retain(|d| {
+            let pos: Vec<&Vec<u32>> = lists.iter().map(|l| {
+                let i = l.binary_search_by_key(d, |&(d, _)| d).unwrap();
+                &l[i].1
+            }).collect();
+            // term i of the phrase at position start + i
+            pos[0].iter().any(|&start| {
+                pos.iter().enumerate().skip(1).all(|(i, p)| p.binary_search(&(start + i as u32)).is_ok())
+            })
+        });
+        docs
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn lex(text: &str) -> Result<Vec<Token>, ParseQueryError> {
+    let mut toks = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            chars.next();
+            toks.push(if c == '(' { Token::Open } else { Token::Close });
+        } else if c == '"' {
+            chars.next();
+            let end = match text[i + 1..].find('"') {
+                Some(end) => i + 1 + end,
+                None => return Err(ParseQueryError::UnclosedQuote),
+            };
+            toks.push(Token::Phrase(tokenize(&text[i + 1..end])));
+            while chars.peek().is_some_and(|&(j, _)| j <= end) {
+                chars.next();
+            }
+        } else {
+            let mut end = text.len();
+            while let Some(&(j, c)) = chars.peek() {
+                if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                    end = j;
+                    break;
+                }
+                chars.next();
+            }
+            let word = &text[i..end];
+            toks.push(match word {
+                "AND" => Token::And,
+                "OR" => Token::Or,
+                "NOT" => Token::Not,
+                _ => {
+                    let (body, prefix) = match word.strip_suffix('*') {
+                        Some(body) => (body, true),
+                        None => (word, false),
+                    };
+                    let mut terms = tokenize(body);
+                    match (terms.len(), prefix) {
+                        (1, true) => Token::Prefix(terms.pop().unwrap()),
+                        (1, false) => Token::Word(terms.pop().unwrap()),
+                        (0, _) => return Err(ParseQueryError::Unexpected(word.to_string())),
+                        // like c++ or e-mail, several terms in a row
+                        _ => Token::Phrase(terms),
+                    }
+                },
+            });
+        }
+    }
+    Ok(toks)
+}
+
+struct Parser {
+    toks: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.toks.get(self.at)
+    }
+
+    fn or(&mut self) -> Result<Query, ParseQueryError> {
+        let mut qs = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.at += 1;
+            qs.push(self.and()?);
+        }
+        Ok(if qs.len() == 1 { qs.pop().unwrap() } else { Query::Or(qs) })
+    }
+
+    // AND may be left out between terms
+    fn and(&mut self) -> Result<Query, ParseQueryError> {
+        let mut qs = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(&Token::And) => self.at += 1,
+                None | Some(&Token::Or) | Some(&Token::Close) => break,
+                _ => {},
+            }
+            qs.push(self.unary()?);
+        }
+        Ok(if qs.len() == 1 { qs.pop().unwrap() } else { Query::And(qs) })
+    }
+
+    fn unary(&mut self) -> Result<Query, ParseQueryError> {
+        let tok = match self.toks.get(self.at) {
+            Some(tok) => tok,
+            None => return Err(ParseQueryError::Empty),
+        };
+        self.at += 1;
+        match *tok {
+            Token::Word(ref t) => Ok(Query::Term(t.clone())),
+            Token::Prefix(ref t) => Ok(Query::Prefix(t.clone())),
+            Token::Phrase(ref ts) => Ok(Query::Phrase(ts.clone())),
+            Token::Not => Ok(Query::Not(Box::new(self.unary()?))),
+            Token::Open => {
+                let q = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(ParseQueryError::UnclosedParen);
+                }
+                self.at += 1;
+                Ok(q)
+            },
+            Token::And => Err(ParseQueryError::Unexpected("AND".to_string())),
+            Token::Or => Err(ParseQueryError::Unexpected("OR".to_string())),
+            Token::Close => Err(ParseQueryError::Unexpected(")".to_string())),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = ParseQueryError;
+
+    fn from_str(s: &str) -> Result<Query, ParseQueryError> {
+        let mut p = Parser {
+            toks: lex(s)?,
+            at: 0,
+        };
+        let q = p.or()?;
+        match p.peek() {
+            None => Ok(q),
+            Some(_) => Err(ParseQueryError::Unexpected(")".to_string())),
+        }
+    }
+}
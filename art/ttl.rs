@@ -0,0 +1,211 @@
+// Art with per-key expiry.
+//
+// A key inserted with a time to live expires when the clock passes its
+// deadline. Reads treat expired keys as missing, get and remove also delete
+// them. Deadlines are kept in a BTreeSet next to the tree, purge_expired
+// deletes the earliest expired keys a few at a time, through Art::delete so
+// nodes shrink as usual. Time comes from a Clock, ManualClock for tests.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::art::Art;
+
+pub trait Clock {
+    // time since some fixed start, never going back
+    fn now(&self) -> Duration;
+}
+
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> MonotonicClock {
+        MonotonicClock { start: Instant::now() }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+// moves only when told to
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock { nanos: AtomicU64::new(0) }
+    }
+
+    // stops at the largest time it can hold
+    pub fn advance(&self, d: Duration) {
+        let d = u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+        let _ = self.nanos.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| Some(n.saturating_add(d)));
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+// a clock shared with the test moving it
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    expires: Option<Duration>,
+}
+
+impl<V> Entry<V> {
+    fn live(&self, now: Duration) -> bool {
+        self.expires.is_none_or(|t| now < t)
+    }
+}
+
+pub struct TtlArt<V, C: Clock = MonotonicClock> {
+    art: Art<Entry<V>>,
+    // (deadline, key) of the keys with a ttl
+    deadlines: BTreeSet<(Duration, Vec<u8>)>,
+    clock: C,
+}
+
+impl<V> TtlArt<V> {
+    pub fn new() -> TtlArt<V> {
+        TtlArt::with_clock(MonotonicClock::new())
+    }
+}
+
+impl<V, C: Clock> TtlArt<V, C> {
+    pub fn with_clock(clock: C) -> TtlArt<V, C> {
+        TtlArt {
+            art: Art::new(),
+            deadlines: BTreeSet::new(),
+            clock,
+        }
+    }
+
+    // keys in the tree, expired ones count until they are deleted
+    pub fn len(&self) -> usize {
+        self.art.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.art.is_empty()
+    }
+
+    // keys with a ttl, expired or not
+    pub fn expiring(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    // the key never expires, any ttl it had is dropped
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, val: V) -> Option<V> {
+        self.put(key.as_ref(), val, None)
+    }
+
+    // a deadline too far to hold in a Duration never comes, the key never expires
+    pub fn insert_with_ttl<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K, val: V, ttl: Duration) -> Option<V> {
+        let deadline = self.clock.now().checked_add(ttl);
+        self.put(key.as_ref(), val, deadline)
+    }
+
+    // returns the old value if it was still live
+    fn put(&mut self, key: &[u8], val: V, expires: Option<Duration>) -> Option<V> {
+        if let Some(t) = expires {
+            self.deadlines.insert((t, key.to_vec()));
+        }
+        let old = self.art.insert(key, Entry { value: val, expires })?;
+        if let Some(t) = old.expires {
+            // the same deadline is inserted again above
+            if Some(t) != expires {
+                self.deadlines.remove(&(t, key.to_vec()));
+            }
+        }
+        if old.live(self.clock.now()) { Some(old.value) } else { None }
+    }
+
+    // the value of a live key, an expired one is deleted
+    pub fn get<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<&V> {
+        let key = key.as_ref();
+        let now = self.clock.now();
+        if !self.art.search(key)?.live(now) {
+            self.remove_entry(key);
+            return None;
+        }
+        self.art.search(key).map(|e| &e.value)
+    }
+
+    pub fn get_mut<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<&mut V> {
+        let key = key.as_ref();
+        let now = self.clock.now();
+        if !self.art.search(key)?.live(now) {
+            self.remove_entry(key);
+            return None;
+        }
+        self.art.search_mut(key).map(|e| &mut e.value)
+    }
+
+    // like get, but an expired key stays until purged
+    pub fn peek<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&V> {
+        let now = self.clock.now();
+        self.art.search(key).filter(|e| e.live(now)).map(|e| &e.value)
+    }
+
+    // time left for a live key, None for a key without ttl or not there
+    pub fn ttl<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<Duration> {
+        let now = self.clock.now();
+        let e = self.art.search(key).filter(|e| e.live(now))?;
+        e.expires.map(|t| t - now)
+    }
+
+    // returns the value if it was still live
+    pub fn remove<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<V> {
+        let e = self.remove_entry(key.as_ref())?;
+        if e.live(self.clock.now()) { Some(e.value) } else { None }
+    }
+
+    fn remove_entry(&mut self, key: &[u8]) -> Option<Entry<V>> {
+        let e = self.art.delete(key)?;
+        if let Some(t) = e.expires {
+            self.deadlines.remove(&(t, key.to_vec()));
+        }
+        Some(e)
+    }
+
+    // delete at most budget expired keys, earliest deadline first, returns
+    // how many. Call it often with a small budget to spread the work.
+    pub fn purge_expired(&mut self, budget: usize) -> usize {
+        let now = self.clock.now();
+        let mut purged = 0;
+        while purged < budget {
+            let (t, key) = match self.deadlines.iter().next() {
+                Some(&(t, ref key)) if t <= now => (t, key.clone()),
+                _ => break,
+            };
+            self.deadlines.remove(&(t, key.clone()));
+            self.art.delete(&key);
+            purged += 1;
+        }
+        purged
+    }
+
+    // live keys and values in key order
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a [u8], &'a V)> + 'a {
+        let now = self.clock.now();
+        self.art.iter().filter(move |(_, e)| e.live(now)).map(|(k, e)| (k, &e.value))
+    }
+}
@@ -29,5 +29,20 @@ fn main() {
         None => println!("Key not exists"),
     }
 
+    t.insert(String::from("20240101-a"), String::from("first"));
+    t.insert(String::from("20240102-b"), String::from("second"));
+    t.insert(String::from("20240103-c"), String::from("third"));
+
+    println!("Number of keys: {}", t.iter().count());
+
+    println!("Keys in [hello, whyisrust):");
+    for (k, vals) in t.range("hello".."whyisrust") {
+        println!("{} => {:?}", k, vals);
+    }
+
+    println!("Latest 2 keys of 2024:");
+    for (k, vals) in t.range("2024".."2025").rev().take(2) {
+        println!("{} => {:?}", k, vals);
+    }
 }
 
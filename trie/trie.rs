@@ -1,5 +1,6 @@
 use std::fmt;
 use std::u8;
+use std::ops::{Bound, RangeBounds};
 
 const TRIE_NODE_SPAN: usize = 256;
 
@@ -157,6 +158,266 @@ impl Trie {
         });
         Some(r)
     }
+
+    // keys and values whose key falls in range, in byte order;
+    // the iterator can also be walked from the back with rev()
+    pub fn range<'a, K, R>(&'a self, range: R) -> Range<'a>
+        where K: AsRef<[u8]> + ?Sized, R: RangeBounds<K> {
+        Range {
+            root: self.root.as_deref(),
+            front: Vec::new(),
+            back: Vec::new(),
+            front_key: Vec::new(),
+            back_key: Vec::new(),
+            lower: own_bound(range.start_bound()),
+            upper: own_bound(range.end_bound()),
+            last_front: None,
+            last_back: None,
+            front_started: false,
+            back_started: false,
+            done: false,
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> Range<'a> {
+        self.range::<[u8], _>(..)
+    }
+}
+
+fn own_bound<K: AsRef<[u8]> + ?Sized>(b: Bound<&K>) -> Bound<Vec<u8>> {
+    match b {
+        Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+        Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+// key is greater than or equal to the lower bound
+fn above_lower(key: &[u8], lower: &Bound<Vec<u8>>) -> bool {
+    match *lower {
+        Bound::Included(ref k) => key >= k.as_slice(),
+        Bound::Excluded(ref k) => key > k.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+// key is less than or equal to the upper bound
+fn below_upper(key: &[u8], upper: &Bound<Vec<u8>>) -> bool {
+    match *upper {
+        Bound::Included(ref k) => key <= k.as_slice(),
+        Bound::Excluded(ref k) => key < k.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+
+struct Frame<'a> {
+    node: &'a TrieNode,
+    // next child slot to look at, moves up from the front and down from the back
+    next: i32,
+    // values of this node not visited yet
+    pending: bool,
+}
+
+pub struct Range<'a> {
+    root: Option<&'a TrieNode>,
+    front: Vec<Frame<'a>>,
+    back: Vec<Frame<'a>>,
+    // key bytes of the path in front/back, the root has no byte
+    front_key: Vec<u8>,
+    back_key: Vec<u8>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    last_front: Option<Vec<u8>>,
+    last_back: Option<Vec<u8>>,
+    front_started: bool,
+    back_started: bool,
+    done: bool,
+}
+
+impl<'a> Range<'a> {
+
+    // put the front cursor on the path of the lower bound,
+    // everything before it is skipped without being walked
+    fn seek_front(&mut self) {
+        let mut node = match self.root {
+            Some(node) => node,
+            None => return,
+        };
+        let k = match self.lower {
+            Bound::Included(ref k) | Bound::Excluded(ref k) => k.clone(),
+            Bound::Unbounded => Vec::new(),
+        };
+        for c in k.iter() {
+            self.front.push(Frame { node, next: *c as i32 + 1, pending: false });
+            match node.child[*c as usize] {
+                Some(ref ptr) => {
+                    self.front_key.push(*c);
+                    node = ptr;
+                },
+                None => return,
+            }
+        }
+        self.front.push(Frame { node, next: 0, pending: true });
+    }
+
+    // put the back cursor on the path of the upper bound
+    fn seek_back(&mut self) {
+        let mut node = match self.root {
+            Some(node) => node,
+            None => return,
+        };
+        let k = match self.upper {
+            Bound::Included(ref k) | Bound::Excluded(ref k) => k.clone(),
+            Bound::Unbounded => {
+                self.back.push(Frame { node, next: u8::MAX as i32, pending: true });
+                return;
+            },
+        };
+        for c in k.iter() {
+            self.back.push(Frame { node, next: *c as i32 - 1, pending: true });
+            match node.child[*c as usize] {
+                Some(ref ptr) => {
+                    self.back_key.push(*c);
+                    node = ptr;
+                },
+                None => return,
+            }
+        }
+        self.back.push(Frame { node, next: -1, pending: true });
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        self.front.clear();
+        self.back.clear();
+    }
+}
+
+impl<'a> Iterator for Range<'a> {
+    type Item = (String, &'a Vec<String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.front_started {
+            self.front_started = true;
+            self.seek_front();
+        }
+
+        while !self.done {
+            let (node, found) = match self.front.last_mut() {
+                Some(top) => {
+                    if top.pending {
+                        top.pending = false;
+                        (top.node, None)
+                    } else {
+                        let mut found = None;
+                        while top.next <= u8::MAX as i32 {
+                            let idx = top.next as usize;
+                            top.next += 1;
+                            if let Some(ref ptr) = top.node.child[idx] {
+                                found = Some((idx as u8, &**ptr));
+                                break;
+                            }
+                        }
+                        (top.node, Some(found))
+                    }
+                },
+                None => break,
+            };
+
+            match found {
+                // visit the values of the node on top
+                None => {
+                    if node.values.is_empty() || !above_lower(&self.front_key, &self.lower) {
+                        continue;
+                    }
+                    let key = self.front_key.clone();
+                    let behind = match self.last_back {
+                        Some(ref b) => key >= *b,
+                        None => false,
+                    };
+                    if behind || !below_upper(&key, &self.upper) {
+                        break;
+                    }
+                    self.last_front = Some(key.clone());
+                    return Some((String::from_utf8_lossy(&key).into_owned(), &node.values));
+                },
+                // go down into the next child
+                Some(Some((c, ch))) => {
+                    self.front_key.push(c);
+                    self.front.push(Frame { node: ch, next: 0, pending: true });
+                },
+                // no children left, go up
+                Some(None) => {
+                    self.front.pop();
+                    self.front_key.pop();
+                },
+            }
+        }
+        self.finish();
+        None
+    }
+}
+
+impl<'a> DoubleEndedIterator for Range<'a> {
+
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if !self.back_started {
+            self.back_started = true;
+            self.seek_back();
+        }
+
+        while !self.done {
+            let (node, found) = match self.back.last_mut() {
+                Some(top) => {
+                    let mut found = None;
+                    while top.next >= 0 {
+                        let idx = top.next as usize;
+                        top.next -= 1;
+                        if let Some(ref ptr) = top.node.child[idx] {
+                            found = Some((idx as u8, &**ptr));
+                            break;
+                        }
+                    }
+                    if found.is_none() && top.pending {
+                        top.pending = false;
+                        (top.node, None)
+                    } else {
+                        (top.node, Some(found))
+                    }
+                },
+                None => break,
+            };
+
+            match found {
+                // children all visited, now the values of the node on top
+                None => {
+                    if node.values.is_empty() || !below_upper(&self.back_key, &self.upper) {
+                        continue;
+                    }
+                    let key = self.back_key.clone();
+                    let behind = match self.last_front {
+                        Some(ref f) => key <= *f,
+                        None => false,
+                    };
+                    if behind || !above_lower(&key, &self.lower) {
+                        break;
+                    }
+                    self.last_back = Some(key.clone());
+                    return Some((String::from_utf8_lossy(&key).into_owned(), &node.values));
+                },
+                Some(Some((c, ch))) => {
+                    self.back_key.push(c);
+                    self.back.push(Frame { node: ch, next: u8::MAX as i32, pending: true });
+                },
+                Some(None) => {
+                    self.back.pop();
+                    self.back_key.pop();
+                },
+            }
+        }
+        self.finish();
+        None
+    }
 }
 
 impl fmt::Display for TrieNode {